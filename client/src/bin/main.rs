@@ -1,27 +1,156 @@
+use chat_client::{
+    connect, default_history_path, find_matches, role_prefix, server_name, tls_connector, Backoff, ClientConfig,
+    Completer, History, Reconnect, Scrollback, Sidebar, Transport,
+};
+use chat_protocol::{markup, ClientFrame, Format, ServerFrame, PROTOCOL_VERSION};
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture, Event, MouseButton, MouseEventKind};
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen
 };
 use futures::{SinkExt, StreamExt};
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Layout},
-    style::{Style, Stylize},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
     text::{Line, Span},
     widgets::{Block, Borders, List, ListItem},
     Terminal,
 };
+use rustls::pki_types::ServerName;
 use std::{
     borrow::Cow,
-    io,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+use tokio::{
+    io::{ReadHalf, WriteHalf},
+    task::JoinHandle,
 };
-use tokio::net::TcpStream;
-use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+use tokio_rustls::TlsConnector;
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec, LinesCodecError};
 use tracing_appender::non_blocking::WorkerGuard;
-use tui_textarea::{Input, Key, TextArea};
+use tui_textarea::{CursorMove, Input, Key, TextArea};
+
+type LineSink = FramedWrite<WriteHalf<Box<dyn Transport>>, LinesCodec>;
+type LineStream = FramedRead<ReadHalf<Box<dyn Transport>>, LinesCodec>;
+type Tls = Option<(TlsConnector, ServerName<'static>)>;
+
+// Mỗi lần thử kết nối chờ tối đa chừng này
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// Hai panel tự làm mới sau mỗi khoảng này
+const SIDEBAR_REFRESH: Duration = Duration::from_secs(30);
+const SIDEBAR_WIDTH: u16 = 22;
+
+// Kết nối tới server (tcp hoặc tls) rồi chuyển sang
+// giao thức json, server trả về frame Welcome
+async fn open(addr: &str, tls: &Tls) -> io::Result<(LineSink, LineStream)> {
+    let tls = tls.as_ref().map(|(connector, name)| (connector, name.clone()));
+    let conn = tokio::time::timeout(CONNECT_TIMEOUT, connect(addr, tls))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))??;
+    // Tách reader và writer từ stream
+    let (reader, writer) = tokio::io::split(conn);
+    let mut sink = FramedWrite::new(writer, LinesCodec::new());
+    let stream = FramedRead::new(reader, LinesCodec::new());
+    let protocol = format!("/protocol {} {PROTOCOL_VERSION}", Format::Json.as_str());
+    sink.send(protocol).await.map_err(io::Error::other)?;
+    Ok((sink, stream))
+}
+
+// Thử kết nối lại cho đến khi được, mỗi lần thất bại chờ lâu hơn
+async fn keep_reconnecting(addr: String, tls: Tls) -> (LineSink, LineStream) {
+    let mut backoff = Backoff::default();
+    loop {
+        tokio::time::sleep(backoff.next_delay()).await;
+        match open(&addr, &tls).await {
+            Ok(conn) => return conn,
+            Err(err) => tracing::warn!("reconnect failed: {err}"),
+        }
+    }
+}
+
+// Chờ dòng tiếp theo từ server, đang mất kết nối thì chờ mãi
+async fn next_line(stream: &mut Option<LineStream>) -> Option<Result<String, LinesCodecError>> {
+    match stream {
+        Some(stream) => stream.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Chờ task kết nối lại xong, không có task thì chờ mãi
+async fn reconnected(task: &mut Option<JoinHandle<(LineSink, LineStream)>>) -> Option<(LineSink, LineStream)> {
+    match task {
+        Some(task) => task.await.ok(),
+        None => std::future::pending().await,
+    }
+}
+
+async fn send_frame(sink: &mut LineSink, frame: &ClientFrame) -> Result<(), LinesCodecError> {
+    sink.send(serde_json::to_string(frame).expect("frame luôn serialize được")).await
+}
+
+// Hỏi lại danh sách phòng và user cho hai panel
+async fn refresh_sidebar(sink: &mut Option<LineSink>, sidebar: &mut Sidebar) {
+    if let Some(sink) = sink.as_mut() {
+        for frame in sidebar.refresh() {
+            let _ = send_frame(sink, &frame).await;
+        }
+    }
+}
+
+// Vào phòng đang chọn trong panel
+async fn join_selected(sink: &mut Option<LineSink>, sidebar: &Sidebar, current_room: &str) {
+    if let (Some(sink), Some(room)) = (sink.as_mut(), sidebar.selected_room())
+        && room != current_room
+    {
+        let _ = send_frame(sink, &ClientFrame::Join { room: room.to_owned(), key: None }).await;
+    }
+}
+
+// Dòng thứ mấy trong khung `area` (không tính viền) ở vị trí chuột
+fn row_in(area: Rect, column: u16, row: u16) -> Option<usize> {
+    let inside = column > area.x
+        && column + 1 < area.x + area.width
+        && row > area.y
+        && row + 1 < area.y + area.height;
+    inside.then(|| usize::from(row - area.y - 1))
+}
+
+// Danh sách phòng kèm số user, phòng đang ở in đậm,
+// phòng đang chọn đảo màu
+fn rooms_list<'a>(sidebar: &'a Sidebar, current_room: &str) -> List<'a> {
+    let items = sidebar.rooms().iter().enumerate().map(|(i, room)| {
+        let mut style = Style::new();
+        if room.name == current_room {
+            style = style.bold();
+        }
+        if i == sidebar.selected() {
+            style = style.reversed();
+        }
+        ListItem::new(format!("{} ({})", room.name, room.users)).style(style)
+    });
+    List::new(items).block(Block::default().borders(Borders::ALL).title("Rooms"))
+}
+
+// User trong phòng, người đang away thì làm mờ
+fn users_list(sidebar: &Sidebar) -> List<'_> {
+    let items = sidebar.users().iter().map(|user| {
+        let style = if user.away.is_some() { Style::new().dim() } else { Style::new() };
+        ListItem::new(format!("{}{}", role_prefix(user.role), user.name)).style(style)
+    });
+    let title = format!("Users ({})", sidebar.users().len());
+    List::new(items).block(Block::default().borders(Borders::ALL).title(title))
+}
 
-// TODO: Thay đổi tuỳ server, mặc định là localhost
-const SERVER_ADD: &str = "127.0.0.1:8080";
-// const SERVER_ADDR: &str = "127.0.0.1:8080";
+// Ghi log ra file, giữ guard đến hết main để log kịp ghi xuống
+fn init_logging(path: &Path) -> io::Result<WorkerGuard> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let (writer, guard) = tracing_appender::non_blocking(file);
+    tracing_subscriber::fmt().with_writer(writer).with_ansi(false).init();
+    Ok(guard)
+}
 
 // Khởi tạo textarea
 fn textarea_new() -> TextArea<'static> {
@@ -34,53 +163,293 @@ fn textarea_new() -> TextArea<'static> {
     textarea
 }
 
-fn messages_to_list(msgs: &[String], min_lines: usize, max_length: usize) -> List<'_> {
-    let mut list_items = Vec::new();
+// Textarea có sẵn nội dung, con trỏ ở cuối dòng
+fn textarea_with(text: &str) -> TextArea<'static> {
+    let mut textarea = textarea_new();
+    textarea.insert_str(text);
+    textarea.move_cursor(CursorMove::End);
+    textarea
+}
 
-    // Lặp các tin nhắn theo thứ tự ngược -> Lấy tin mới nhất trước
-    'outer: for msg in msgs.iter().rev() {
-        let user_msg = msg.contains(':');
-        // Ngắt dòng đẹp
-        let lines = textwrap::wrap(
-            msg,
-            textwrap::Options::new(max_length)
-                .wrap_algorithm(textwrap::WrapAlgorithm::new_optimal_fit()),
-        );
-        let mut styled_lines = Vec::new();
-        if user_msg {
-            let mut lines = lines.into_iter();
-            // Lấy dòng đầu tiên
-            let first_line = lines.next().unwrap();
-            let mut parts = first_line.split(':');
-            let mut first_styled_line = Vec::new();
-            // Tên user -> in đậm
-            first_styled_line.push(parts.next().unwrap().to_owned().bold());
-            // Nội dung -> giữ nguyên
-            for part in parts {
-                first_styled_line.push(Span::raw(":"));
-                first_styled_line.push(part.to_owned().into());
+// Ô nhập từ khoá tìm kiếm
+fn search_box_new() -> TextArea<'static> {
+    let mut search_box = TextArea::default();
+    search_box.set_cursor_style(Style::default());
+    search_box.set_placeholder_text("Search messages...");
+    search_box
+}
+
+// Chế độ tìm kiếm, bật bằng Ctrl+F
+struct Search {
+    input: TextArea<'static>,
+    // Tin khớp đang được chọn
+    current: Option<usize>,
+}
+
+impl Search {
+    fn query(&self) -> &str {
+        self.input.lines().first().map_or("", String::as_str)
+    }
+}
+
+// Chỗ khớp từ khoá -> nền vàng
+const MATCH_STYLE: Style = Style::new().bg(Color::Yellow).fg(Color::Black);
+
+// Tin nhắn đã được xử lý để hiển thị
+// `mention` là tin có @tên mình
+enum Message {
+    Chat { from: String, spans: Vec<markup::Span>, mention: bool },
+    // Tin nhắn /me, hiện dạng "* alice waves"
+    Action { from: String, spans: Vec<markup::Span>, mention: bool },
+    // Tin nhắn riêng, `label` là "[from X]" hoặc "[to X]"
+    Direct { label: String, text: String },
+    System(String),
+    Error(String),
+}
+
+impl Message {
+    // Chuyển frame từ server thành tin nhắn, `me` là tên hiện tại
+    fn from_frame(frame: &ServerFrame, me: &str) -> Self {
+        match frame {
+            ServerFrame::Chat { from, text, spans, mention } => Message::Chat {
+                from: from.clone(),
+                spans: spans_or_plain(spans, text),
+                mention: *mention,
+            },
+            ServerFrame::Action { from, text, spans, mention } => Message::Action {
+                from: from.clone(),
+                spans: spans_or_plain(spans, text),
+                mention: *mention,
+            },
+            // Được nhắc tên ở phòng khác -> hiện kèm tên phòng
+            ServerFrame::Mentioned { room, from, text, spans } => Message::Chat {
+                from: format!("[{room}] {from}"),
+                spans: spans_or_plain(spans, text),
+                mention: true,
+            },
+            ServerFrame::Direct { from, to, text } => Message::Direct {
+                label: if to == me {
+                    format!("[from {from}]")
+                } else {
+                    format!("[to {to}]")
+                },
+                text: text.clone(),
+            },
+            ServerFrame::Error { text } => Message::Error(text.clone()),
+            frame => Message::System(frame.to_text(me)),
+        }
+    }
+
+    fn is_mention(&self) -> bool {
+        matches!(self, Message::Chat { mention: true, .. } | Message::Action { mention: true, .. })
+    }
+
+    // Nội dung dạng chữ, dùng để tìm kiếm
+    fn text(&self) -> String {
+        self.to_spans().iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        !find_matches(&self.text(), query).is_empty()
+    }
+
+    // Các đoạn chữ đã chỉnh kiểu, chưa ngắt dòng
+    fn to_spans(&self) -> Vec<Span<'static>> {
+        match self {
+            // Tên user -> in đậm, nội dung -> theo markup
+            Message::Chat { from, spans, .. } => {
+                let mut line = vec![from.clone().bold(), Span::raw(": ")];
+                line.extend(styled_spans(spans));
+                line
             }
-            styled_lines.push(Line::from(first_styled_line));
-            for line in lines {
-                styled_lines.push(Line::from(line.into_owned()));
+            // Hành động -> in nghiêng cả dòng
+            Message::Action { from, spans, .. } => {
+                let mut line = vec![Span::raw("* "), from.clone().bold(), Span::raw(" ")];
+                line.extend(styled_spans(spans));
+                line.into_iter().map(|span| span.italic()).collect()
             }
-        } else {
+            // Tin nhắn riêng -> màu tím để phân biệt với tin trong phòng
+            Message::Direct { label, text } => vec![
+                label.clone().fg(Color::Magenta).bold(),
+                Span::raw(" "),
+                text.clone().fg(Color::Magenta),
+            ],
             // Nếu là thông báo hệ thống -> làm mờ + in nghiêng
-            styled_lines.extend(
-                lines
-                    .into_iter()
-                    .map(|line| line.into_owned().dim().italic().into()),
-            );
+            Message::System(text) => vec![text.clone().dim().italic()],
+            // Lỗi -> màu đỏ
+            Message::Error(text) => vec![text.clone().fg(Color::Red)],
+        }
+    }
+}
+
+// Server không gửi spans -> coi cả tin là chữ thường
+fn spans_or_plain(spans: &[markup::Span], text: &str) -> Vec<markup::Span> {
+    if spans.is_empty() && !text.is_empty() {
+        return vec![markup::Span::Plain { text: text.to_owned() }];
+    }
+    spans.to_vec()
+}
+
+// Chuyển markup thành span có kiểu của ratatui
+fn styled_spans(spans: &[markup::Span]) -> Vec<Span<'static>> {
+    let mut styled = Vec::new();
+    for span in spans {
+        match span {
+            markup::Span::Plain { text } => styled.push(Span::raw(text.clone())),
+            markup::Span::Bold { text } => styled.push(text.clone().bold()),
+            markup::Span::Italic { text } => styled.push(text.clone().italic()),
+            markup::Span::Code { text } => styled.push(text.clone().fg(Color::Yellow)),
+            markup::Span::Link { text, url } => {
+                styled.push(text.clone().fg(Color::Cyan).underlined());
+                // Link có chữ riêng -> hiện thêm địa chỉ thật
+                if text != url {
+                    styled.push(format!(" ({url})").dim());
+                }
+            }
+        }
+    }
+    styled
+}
+
+// Tô màu các chỗ khớp từ khoá, kể cả chỗ vắt qua nhiều đoạn
+fn highlight(spans: Vec<Span<'static>>, query: &str) -> Vec<Span<'static>> {
+    let text: String = spans.iter().map(|span| span.content.as_ref()).collect();
+    let matches = find_matches(&text, query);
+    if matches.is_empty() {
+        return spans;
+    }
+    let mut highlighted = Vec::new();
+    // Vị trí của đoạn đang xét trong `text`
+    let mut start = 0;
+    for span in spans {
+        let end = start + span.content.len();
+        let mut pos = start;
+        for found in matches.iter().filter(|found| found.start < end && found.end > start) {
+            let (from, to) = (found.start.max(start), found.end.min(end));
+            if from > pos {
+                highlighted.push(Span::styled(text[pos..from].to_owned(), span.style));
+            }
+            highlighted.push(Span::styled(text[from..to].to_owned(), span.style.patch(MATCH_STYLE)));
+            pos = to;
+        }
+        if pos < end {
+            highlighted.push(Span::styled(text[pos..end].to_owned(), span.style));
         }
+        start = end;
+    }
+    highlighted
+}
+
+// Ngắt dòng theo từ mà vẫn giữ kiểu của từng đoạn,
+// từ dài hơn cả dòng thì cắt theo ký tự
+fn wrap_spans(spans: Vec<Span<'static>>, width: usize) -> Vec<Line<'static>> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut line_width = 0;
+    // Dòng hiện tại là do ngắt tự động -> bỏ khoảng trắng đầu dòng
+    let mut wrapped = false;
+    for span in spans {
+        let style = span.style;
+        for (i, part) in span.content.split('\n').enumerate() {
+            // Xuống dòng có sẵn trong tin nhắn
+            if i > 0 {
+                lines.push(Line::from(std::mem::take(&mut line)));
+                line_width = 0;
+                wrapped = false;
+            }
+            for word in part.split_inclusive(' ') {
+                let mut rest = word;
+                while !rest.is_empty() {
+                    if line_width == 0 && wrapped {
+                        rest = rest.trim_start();
+                        if rest.is_empty() {
+                            break;
+                        }
+                    }
+                    let fits = width.saturating_sub(line_width);
+                    let word_width = textwrap::core::display_width(rest.trim_end());
+                    if word_width > fits && line_width > 0 {
+                        lines.push(Line::from(std::mem::take(&mut line)));
+                        line_width = 0;
+                        wrapped = true;
+                        continue;
+                    }
+                    let piece = if word_width > fits { split_at_width(rest, fits) } else { rest };
+                    line.push(Span::styled(piece.to_owned(), style));
+                    line_width += textwrap::core::display_width(piece);
+                    rest = &rest[piece.len()..];
+                }
+            }
+        }
+    }
+    lines.push(Line::from(line));
+    lines
+}
+
+// Phần đầu của `text` vừa với `width` cột, ít nhất một ký tự
+fn split_at_width(text: &str, width: usize) -> &str {
+    let end = text
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take_while(|&end| textwrap::core::display_width(&text[..end]) <= width)
+        .last()
+        .unwrap_or_else(|| text.chars().next().map_or(0, char::len_utf8));
+    &text[..end]
+}
+
+// Tin khớp gần nhất cũ hơn (hoặc mới hơn) tin `from`,
+// chưa chọn tin nào thì tìm từ tin mới nhất
+fn find_message(msgs: &[Message], query: &str, from: Option<usize>, older: bool) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    if older {
+        msgs[..from.unwrap_or(msgs.len())].iter().rposition(|msg| msg.matches(query))
+    } else {
+        let from = from.map_or(msgs.len(), |from| from + 1);
+        msgs.iter().skip(from).position(|msg| msg.matches(query)).map(|i| from + i)
+    }
+}
+
+// Số dòng của tin nhắn khi hiển thị với độ rộng `width`
+fn line_count(msg: &Message, width: usize) -> usize {
+    wrap_spans(msg.to_spans(), width).len()
+}
+
+// `query` khác rỗng -> tô màu các chỗ khớp
+fn messages_to_list<'a>(
+    msgs: &'a [Message],
+    min_lines: usize,
+    max_length: usize,
+    scrollback: &mut Scrollback,
+    query: &str,
+) -> List<'a> {
+    // Các dòng từ mới nhất trở lên, chỉ lấy đủ tới chỗ đang xem
+    let mut lines = Vec::new();
+    let wanted = scrollback.offset().saturating_add(min_lines);
+
+    // Lặp các tin nhắn theo thứ tự ngược -> Lấy tin mới nhất trước
+    'outer: for msg in msgs.iter().rev() {
+        let mut spans = msg.to_spans();
+        if !query.is_empty() {
+            spans = highlight(spans, query);
+        }
+        let styled_lines = wrap_spans(spans, max_length);
+        // Tin nhắc tên mình -> tô nền cả dòng cho dễ thấy
+        let style = if msg.is_mention() { Style::new().bg(Color::DarkGray) } else { Style::new() };
         // Duyệt các line đã được chỉnh kiểu theo thứ tự ngược -> render tin mới nhất trước
         for line in styled_lines.into_iter().rev() {
-            list_items.push(ListItem::new(line));
-            if list_items.len() >= min_lines {
+            lines.push(ListItem::new(line).style(style));
+            if lines.len() >= wanted {
                 break 'outer;
             }
         }
     }
 
+    // Hết tin nhắn trước khi đủ dòng -> không cuộn quá tin cũ nhất
+    scrollback.clamp(lines.len(), min_lines);
+    let mut list_items: Vec<_> = lines.into_iter().skip(scrollback.offset()).take(min_lines).collect();
     while list_items.len() < min_lines {
         list_items.push(ListItem::new(Cow::from("")));
     }
@@ -90,10 +459,25 @@ fn messages_to_list(msgs: &[String], min_lines: usize, max_length: usize) -> Lis
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let addr = SERVER_ADD;
+    let config = ClientConfig::parse();
+    let addr = config.addr.as_str();
+
+    let _guard = match &config.log_file {
+        Some(path) => Some(init_logging(path)?),
+        None => None,
+    };
+
+    // Cấu hình TLS nếu được yêu cầu
+    let tls = match config.tls_options() {
+        Some(options) => {
+            let name = server_name(config.server_name.as_deref().unwrap_or(addr))?;
+            Some((tls_connector(&options)?, name))
+        }
+        None => None,
+    };
 
-    // Tạo kết nối Tcp đến server
-    let mut conn = match TcpStream::connect(addr).await {
+    // Tạo kết nối đến server
+    let (mut sink, stream) = match open(addr, &tls).await {
         Ok(conn) => conn,
         Err(err) => {
             match err.kind() {
@@ -107,56 +491,149 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
-    // Tách reader và writer từ stream
-    let (reader, writer) = conn.split();
-    let mut sink = FramedWrite::new(writer, LinesCodec::new());
-    let mut stream = FramedRead::new(reader, LinesCodec::new());
+    // Đổi tên, vào phòng theo cấu hình
+    for frame in config.startup_frames() {
+        send_frame(&mut sink, &frame).await?;
+    }
+    // Mất kết nối thì cả hai là None cho đến khi kết nối lại được
+    let mut sink = Some(sink);
+    let mut stream = Some(stream);
+    let mut reconnect = Reconnect::default();
+    let mut reconnect_task = None;
 
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
 
-    let _guard: Option<WorkerGuard> = None;
-
     // Kích hoạt raw mode của terminal: Không echo ký tự nhập vào, Không xử lý Ctr+C...
     enable_raw_mode()?;
 
     // Chuyển màn hình terminal sang màn hình UI
-    crossterm::execute!(stdout, EnterAlternateScreen,)?;
+    crossterm::execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
 
     // Tạo backend cho ratatui -> Tạo terminal từ backend đó để vẽ UI
     let backend = CrosstermBackend::new(stdout);
     let mut term = Terminal::new(backend)?;
 
     let mut textarea = textarea_new();
+    // Lịch sử dòng đã gửi, đọc lỗi thì dùng lịch sử trống
+    let mut history = match default_history_path().map(|path| History::load(&path)) {
+        Some(Ok(history)) => history,
+        Some(Err(err)) => {
+            tracing::warn!("can't load input history: {err}");
+            History::default()
+        }
+        None => History::default(),
+    };
+    let mut completer = Completer::default();
     // Tạo layout cho UI: Chiếm 100% chiều cao, chiều cao tối thiểu 3 ô
     let layout = Layout::default().constraints(
         [Constraint::Percentage(100), Constraint::Min(3)]
     );
 
-    let mut messages: Vec<String> = Vec::new();
+    let mut messages: Vec<Message> = Vec::new();
+    // Số tin đã tính vào scrollback
+    let mut counted = 0;
+    let mut scrollback = Scrollback::default();
+    let mut search: Option<Search> = None;
+    // Kích thước khung tin nhắn lần vẽ gần nhất (cao, rộng)
+    let mut msgs_size: (usize, usize) = (0, 0);
     let mut current_room = "main".to_owned();
+    let mut me = String::new();
+    // Số lần bị nhắc tên từ lần gửi tin gần nhất
+    let mut mentions = 0;
+    let mut sidebar = Sidebar::default();
+    // Vị trí panel phòng lần vẽ gần nhất, để biết chuột bấm vào phòng nào
+    let mut rooms_area = Rect::default();
+    let mut sidebar_timer = tokio::time::interval_at(
+        tokio::time::Instant::now() + SIDEBAR_REFRESH,
+        SIDEBAR_REFRESH,
+    );
 
     // Tạo eventstream cho phép nhận sự kiện bàn phím như async stream
     let mut term_stream = crossterm::event::EventStream::new();
 
-    loop {
+    'ui: loop {
+        // Tin mới đến trong lúc đang cuộn lên -> giữ nguyên chỗ đang xem
+        for msg in &messages[counted..] {
+            scrollback.push(line_count(msg, msgs_size.1));
+        }
+        counted = messages.len();
+        let reconnecting = reconnect_task.is_some();
         let draw_res = term.draw(|f| {
             let chunks = layout.split(f.size());
 
-            let msgs_height = chunks[0].height - 2;
-            let msgs_width = chunks[0].width - 2;
-            let msgs_title = format!("Room - {current_room}");
+            // Panel phòng | tin nhắn | panel user
+            let mut columns = Vec::new();
+            if sidebar.show_rooms {
+                columns.push(Constraint::Length(SIDEBAR_WIDTH));
+            }
+            columns.push(Constraint::Min(20));
+            if sidebar.show_users {
+                columns.push(Constraint::Length(SIDEBAR_WIDTH));
+            }
+            let columns = Layout::default().direction(Direction::Horizontal).constraints(columns).split(chunks[0]);
+            let mut columns = columns.iter().copied();
+            rooms_area = match sidebar.show_rooms {
+                true => columns.next().unwrap_or_default(),
+                false => Rect::default(),
+            };
+            let msgs_area = columns.next().unwrap_or_default();
+            if let Some(users_area) = columns.next() {
+                f.render_widget(users_list(&sidebar), users_area);
+            }
+            if sidebar.show_rooms {
+                f.render_widget(rooms_list(&sidebar, &current_room), rooms_area);
+            }
+
+            let msgs_height = msgs_area.height.saturating_sub(2);
+            let msgs_width = msgs_area.width.saturating_sub(2);
+            msgs_size = (msgs_height.into(), msgs_width.into());
+            let mut msgs_title = match mentions {
+                0 => format!("Room - {current_room}"),
+                1 => format!("Room - {current_room} (1 mention)"),
+                n => format!("Room - {current_room} ({n} mentions)"),
+            };
+            if reconnecting {
+                msgs_title.push_str(" - reconnecting…");
+            }
+            let query = search.as_ref().map(|search| search.query().to_owned()).unwrap_or_default();
+            let query = query.as_str();
 
             // Biến msgs thành widget List<'_>
             let msgs = messages_to_list(
                 &messages,
                 msgs_height.into(),
                 msgs_width.into(),
-            ).block(Block::default().borders(Borders::ALL).title(msgs_title));
-
-            f.render_widget(msgs, chunks[0]);
+                &mut scrollback,
+                query,
+            );
+            // Đang cuộn lên -> báo ở đáy khung nếu có tin mới bên dưới
+            let below = match scrollback.unseen() {
+                _ if !scrollback.is_scrolled() => String::new(),
+                0 => "Scrolled up, End to go back ".to_owned(),
+                1 => "1 new message below, End to go back ".to_owned(),
+                n => format!("{n} new messages below, End to go back "),
+            };
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title(msgs_title)
+                .title_bottom(Line::from(below.bold()).right_aligned());
+            f.render_widget(msgs.block(block), msgs_area);
 
-            f.render_widget(&textarea, chunks[1]);
+            match &mut search {
+                Some(search) => {
+                    let found = messages.iter().filter(|msg| msg.matches(query)).count();
+                    let title = match found {
+                        _ if query.is_empty() => "Search".to_owned(),
+                        0 => "Search - no matches".to_owned(),
+                        1 => "Search - 1 match, Enter/Up older, Down newer, Esc to close".to_owned(),
+                        n => format!("Search - {n} matches, Enter/Up older, Down newer, Esc to close"),
+                    };
+                    search.input.set_block(Block::default().borders(Borders::ALL).title(title));
+                    f.render_widget(&search.input, chunks[1]);
+                }
+                None => f.render_widget(&textarea, chunks[1]),
+            }
         });
 
         match draw_res {
@@ -173,12 +650,127 @@ async fn main() -> anyhow::Result<()> {
                         Err(_) => break
                     };
 
-                    match event.into() {
+                    // Chuột: bấm vào phòng để vào, cuộn để xem tin cũ
+                    if let Event::Mouse(mouse) = &event {
+                        match mouse.kind {
+                            MouseEventKind::Down(MouseButton::Left) => {
+                                if let Some(index) = row_in(rooms_area, mouse.column, mouse.row)
+                                    && index < sidebar.rooms().len()
+                                {
+                                    sidebar.select(index);
+                                    join_selected(&mut sink, &sidebar, &current_room).await;
+                                }
+                            }
+                            MouseEventKind::ScrollUp => scrollback.up(3),
+                            MouseEventKind::ScrollDown => scrollback.down(3),
+                            _ => (),
+                        }
+                        continue;
+                    }
+
+                    let (height, width) = msgs_size;
+                    let input: Input = event.into();
+                    match input {
                         // Sự kiện thoát
-                        Input {key: Key::Esc, ..} |
                         Input {key: Key::Char('c'), ctrl: true, ..} |
                         Input {key: Key::Char('d'), ctrl: true, ..} => break,
 
+                        // Cuộn danh sách tin nhắn theo trang
+                        Input {key: Key::PageUp, ..} => {
+                            scrollback.up(height.saturating_sub(1).max(1));
+                            continue;
+                        }
+                        Input {key: Key::PageDown, ..} => {
+                            scrollback.down(height.saturating_sub(1).max(1));
+                            continue;
+                        }
+
+                        // F2, F3 bật tắt panel phòng, panel user
+                        Input {key: Key::F(2), ..} => {
+                            sidebar.show_rooms = !sidebar.show_rooms;
+                            continue;
+                        }
+                        Input {key: Key::F(3), ..} => {
+                            sidebar.show_users = !sidebar.show_users;
+                            continue;
+                        }
+                        // Alt+Up, Alt+Down chọn phòng, Alt+Enter vào phòng đó
+                        Input {key: Key::Up, alt: true, ..} => {
+                            sidebar.show_rooms = true;
+                            sidebar.select_prev();
+                            continue;
+                        }
+                        Input {key: Key::Down, alt: true, ..} => {
+                            sidebar.show_rooms = true;
+                            sidebar.select_next();
+                            continue;
+                        }
+                        Input {key: Key::Enter, alt: true, ..} => {
+                            join_selected(&mut sink, &sidebar, &current_room).await;
+                            continue;
+                        }
+                        _ => (),
+                    }
+
+                    // Đang tìm kiếm -> phím dùng cho ô từ khoá
+                    if let Some(search_state) = &mut search {
+                        let older = match input {
+                            Input {key: Key::Esc, ..} => {
+                                search = None;
+                                continue;
+                            }
+                            Input {key: Key::Enter | Key::Up, ..} |
+                            Input {key: Key::Char('f'), ctrl: true, ..} => true,
+                            Input {key: Key::Down, ..} => false,
+                            // Đổi từ khoá -> tìm lại từ tin mới nhất
+                            input => {
+                                if !search_state.input.input_without_shortcuts(input) {
+                                    continue;
+                                }
+                                search_state.current = None;
+                                true
+                            }
+                        };
+                        let query = search_state.query();
+                        if let Some(found) = find_message(&messages, query, search_state.current, older) {
+                            search_state.current = Some(found);
+                            let below = messages[found + 1..].iter().map(|msg| line_count(msg, width)).sum();
+                            scrollback.scroll_to(below);
+                        }
+                        continue;
+                    }
+
+                    match input {
+                        Input {key: Key::Esc, ..} => break,
+
+                        // Bật chế độ tìm kiếm
+                        Input {key: Key::Char('f'), ctrl: true, ..} => {
+                            search = Some(Search { input: search_box_new(), current: None });
+                        }
+
+                        // Ô nhập trống thì Home/End cuộn lên đầu, xuống cuối
+                        Input {key: Key::Home, ..} if textarea.is_empty() => scrollback.top(),
+                        Input {key: Key::End, ..} if textarea.is_empty() => scrollback.bottom(),
+
+                        // Up/Down gọi lại các dòng đã gửi
+                        Input {key: Key::Up, ..} => {
+                            if let Some(line) = history.older(&textarea.lines().join("\n")) {
+                                textarea = textarea_with(line);
+                            }
+                        }
+                        Input {key: Key::Down, ..} => {
+                            if let Some(line) = history.newer() {
+                                textarea = textarea_with(line);
+                            }
+                        }
+
+                        // Tab hoàn thành lệnh, tên user, tên phòng
+                        Input {key: Key::Tab, ..} => {
+                            if let Some(line) = completer.complete(&textarea.lines().join("\n"), &sidebar) {
+                                textarea = textarea_with(&line);
+                            }
+                        }
+
                         // Nhấn phím Enter
                         Input {key: Key::Enter, ..} => {
                             if textarea.is_empty() {
@@ -186,13 +778,41 @@ async fn main() -> anyhow::Result<()> {
                             }
                             // Gửi tin nhắn lên server
                             for line in textarea.into_lines() {
-                                tracing::info!("SENT {line}");
-                                match sink.send(line).await {
-                                    Ok(_) => (),
+                                if let Err(err) = history.push(&line) {
+                                    tracing::warn!("can't save input history: {err}");
+                                }
+                                let frame = match ClientFrame::from_line(&line) {
+                                    Ok(frame) => frame,
+                                    // Lệnh không hợp lệ -> báo lỗi tại chỗ
+                                    Err(err) => {
+                                        messages.push(Message::Error(err.to_string()));
+                                        continue;
+                                    }
+                                };
+                                // Thoát hẳn, Quit được gửi lúc dọn dẹp
+                                if frame == ClientFrame::Quit {
+                                    break 'ui;
+                                }
+                                let Some(sink) = sink.as_mut() else {
+                                    messages.push(Message::Error("Not connected, wait for the reconnect".into()));
+                                    continue;
+                                };
+                                // Không ghi mật khẩu vào log
+                                match frame {
+                                    ClientFrame::Register { .. } | ClientFrame::Login { .. } => {
+                                        tracing::info!("SENT {}", line.split_whitespace().next().unwrap_or_default())
+                                    }
+                                    _ => tracing::info!("SENT {line}"),
+                                }
+                                match send_frame(sink, &frame).await {
+                                    Ok(_) => reconnect.sent(&frame),
                                     Err(_) => break
                                 };
                             }
                             textarea = textarea_new();
+                            // Đã trả lời -> coi như đọc hết các lần nhắc tên
+                            mentions = 0;
+                            scrollback.bottom();
                         }
                         // Các sự kiện còn lại không xử lý (Backspace, Delete,...)
                         input => {
@@ -204,32 +824,122 @@ async fn main() -> anyhow::Result<()> {
                 }
             },
 
+            // Định kỳ làm mới hai panel nếu đang hiện
+            _ = sidebar_timer.tick() => {
+                if sidebar.show_rooms || sidebar.show_users {
+                    refresh_sidebar(&mut sink, &mut sidebar).await;
+                }
+            },
+
+            // Kết nối lại được -> khôi phục phiên cũ
+            conn = reconnected(&mut reconnect_task) => {
+                reconnect_task = None;
+                let Some((mut new_sink, new_stream)) = conn else {
+                    break;
+                };
+                tracing::info!("reconnected");
+                for frame in reconnect.reconnected() {
+                    let _ = send_frame(&mut new_sink, &frame).await;
+                }
+                messages.push(Message::System("Reconnected".into()));
+                sink = Some(new_sink);
+                stream = Some(new_stream);
+            },
+
             // Nhận tin nhắn
-            tcp_event = stream.next() => match tcp_event {
-                Some(event) => {
-                    let server_msg = match event {
-                        Ok(msg) => msg,
-                        Err(_) => break
+            tcp_event = next_line(&mut stream) => match tcp_event {
+                Some(Ok(server_msg)) => {
+                    tracing::info!("GOT {server_msg}");
+                    // Các dòng trước khi chuyển sang json là văn bản thường
+                    let frame = match serde_json::from_str(&server_msg) {
+                        Ok(frame) => frame,
+                        Err(_) => {
+                            // Trang help gửi lúc kết nối cũng nằm ở đây
+                            completer.learn_commands(&server_msg);
+                            messages.push(Message::System(server_msg));
+                            continue;
+                        }
                     };
-                    // Đổi tên phòng
-                    if server_msg.starts_with("You joined ") {
-                        let room_name = server_msg
-                            .split_ascii_whitespace()
-                            .nth(2)
-                            .unwrap();
-                        current_room = room_name.to_owned();
+                    match &frame {
+                        // Server xác nhận giao thức -> lưu tên và phòng
+                        ServerFrame::Welcome { name, room, resume, .. } => {
+                            me = name.clone();
+                            current_room = room.clone();
+                            reconnect.welcome(name, resume.as_deref());
+                            refresh_sidebar(&mut sink, &mut sidebar).await;
+                            continue;
+                        }
+                        // Phiên cũ hết hạn -> tự đổi tên, vào phòng lại
+                        ServerFrame::Error { .. } => {
+                            if let Some(sink) = sink.as_mut() {
+                                for frame in reconnect.error() {
+                                    let _ = send_frame(sink, &frame).await;
+                                }
+                            }
+                        }
+                        // Đổi phòng
+                        ServerFrame::Joined { room, user } if *user == me => {
+                            current_room = room.clone();
+                            reconnect.joined(room);
+                            refresh_sidebar(&mut sink, &mut sidebar).await;
+                        }
+                        // Lấy danh sách lệnh để Tab hoàn thành
+                        ServerFrame::Help { text } => completer.learn_commands(text),
+                        // Đổi tên
+                        ServerFrame::Renamed { from, to } if *from == me => {
+                            me = to.clone();
+                        }
+                        // Lịch sử phòng -> hiển thị từng tin kèm giờ gửi
+                        ServerFrame::History { room, messages: history } if !history.is_empty() => {
+                            messages.push(Message::System(format!("History of {room}")));
+                            for entry in history {
+                                let from = format!("[{}] {}", entry.time(), entry.from);
+                                let spans = spans_or_plain(&entry.spans, &entry.text);
+                                messages.push(if entry.action {
+                                    Message::Action { from, spans, mention: false }
+                                } else {
+                                    Message::Chat { from, spans, mention: false }
+                                });
+                            }
+                            continue;
+                        }
+                        _ => (),
                     }
-                    tracing::info!("GOT {server_msg}");
-                    messages.push(server_msg);
+                    // Trả lời cho lần tự làm mới panel -> không hiện ra
+                    if !sidebar.apply(&frame, &me, &current_room) {
+                        continue;
+                    }
+                    let message = Message::from_frame(&frame, &me);
+                    // Bị nhắc tên -> đếm lên và rung chuông terminal
+                    if message.is_mention() {
+                        mentions += 1;
+                        let backend = term.backend_mut();
+                        let _ = backend.write_all(b"\x07").and_then(|_| backend.flush());
+                    }
+                    messages.push(message);
                 },
-                None => break
+                // Mất kết nối -> kết nối lại ở nền, vẫn dùng được giao diện
+                Some(Err(_)) | None => {
+                    tracing::warn!("disconnected");
+                    sink = None;
+                    stream = None;
+                    reconnect.disconnected(&me, &current_room);
+                    sidebar.disconnected();
+                    messages.push(Message::Error("Disconnected from server, reconnecting…".into()));
+                    reconnect_task = Some(tokio::spawn(keep_reconnecting(addr.to_owned(), tls.clone())));
+                }
             }
         }
     }
 
+    // Báo server là thoát hẳn để phiên không bị giữ lại chờ khôi phục
+    if let Some(sink) = sink.as_mut() {
+        let _ = send_frame(sink, &ClientFrame::Quit).await;
+    }
+
     // Tắt raw mode, thoát về màn hình terminal -> Kết thúc
     disable_raw_mode()?;
-    crossterm::execute!(term.backend_mut(), LeaveAlternateScreen,)?;
+    crossterm::execute!(term.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     term.show_cursor()?;
     Ok(())
 }
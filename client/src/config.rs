@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use chat_protocol::ClientFrame;
+use clap::{CommandFactory, Parser};
+use serde::Deserialize;
+use crate::TlsOptions;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+// Flag trên dòng lệnh ghi đè profile trong file cấu hình,
+// profile ghi đè giá trị mặc định
+#[derive(Parser, Debug, Default)]
+#[command(name = "chat-client", version, about = "Tokio chat client")]
+pub struct Cli {
+    /// File cấu hình TOML, mặc định ~/.config/tokio-chat/client.toml
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Profile server trong file cấu hình
+    #[arg(short, long)]
+    pub profile: Option<String>,
+    /// Địa chỉ server, mặc định 127.0.0.1:8080
+    #[arg(short, long)]
+    pub addr: Option<String>,
+    /// Đổi sang tên này sau khi kết nối
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Vào phòng này sau khi kết nối
+    #[arg(short, long)]
+    pub room: Option<String>,
+    /// Ghi log vào file này, không ghi log nếu bỏ trống
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+    /// Kết nối bằng TLS
+    #[arg(long)]
+    pub tls: bool,
+    /// Tin cậy thêm CA này (PEM), tự bật --tls
+    #[arg(long)]
+    pub ca_cert: Option<PathBuf>,
+    /// Chỉ chấp nhận đúng chứng chỉ server này (PEM), tự bật --tls
+    #[arg(long)]
+    pub pin_cert: Option<PathBuf>,
+    /// Tên server để xác thực chứng chỉ, mặc định lấy từ địa chỉ
+    #[arg(long)]
+    pub server_name: Option<String>,
+}
+
+// Nội dung file cấu hình, mỗi server là một profile
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    // Profile dùng khi không có --profile
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub addr: Option<String>,
+    pub name: Option<String>,
+    pub room: Option<String>,
+    pub log_file: Option<PathBuf>,
+    pub tls: bool,
+    pub ca_cert: Option<PathBuf>,
+    pub pin_cert: Option<PathBuf>,
+    pub server_name: Option<String>,
+}
+
+// Cấu hình sau khi đã gộp flag, profile và mặc định
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub addr: String,
+    pub name: Option<String>,
+    pub room: Option<String>,
+    pub log_file: Option<PathBuf>,
+    pub tls: bool,
+    pub ca_cert: Option<PathBuf>,
+    pub pin_cert: Option<PathBuf>,
+    pub server_name: Option<String>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_ADDR.to_owned(),
+            name: None,
+            room: None,
+            log_file: None,
+            tls: false,
+            ca_cert: None,
+            pin_cert: None,
+            server_name: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "can't read {}: {err}", path.display()),
+            Self::Parse(path, err) => write!(f, "can't parse {}: {err}", path.display()),
+            Self::UnknownProfile(name) => write!(f, "no profile named {name} in the config file"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// ~/.config/tokio-chat/client.toml, theo XDG_CONFIG_HOME nếu có
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("tokio-chat").join("client.toml"))
+}
+
+impl ConfigFile {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Read(path.to_owned(), err))?;
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_owned(), err))
+    }
+}
+
+impl ClientConfig {
+    // Đọc tham số dòng lệnh, lỗi thì in kiểu clap rồi thoát
+    pub fn parse() -> Self {
+        match Self::from_cli(Cli::parse()) {
+            Ok(config) => config,
+            Err(err) => Cli::command()
+                .error(clap::error::ErrorKind::InvalidValue, err)
+                .exit(),
+        }
+    }
+
+    pub fn from_cli(cli: Cli) -> Result<Self, ConfigError> {
+        // File chỉ định bằng --config thì bắt buộc phải có,
+        // file mặc định thì không có cũng được
+        let file = match &cli.config {
+            Some(path) => ConfigFile::from_file(path)?,
+            None => match default_config_path() {
+                Some(path) if path.exists() => ConfigFile::from_file(&path)?,
+                _ => ConfigFile::default(),
+            },
+        };
+        let profile = match cli.profile.or(file.default_profile) {
+            Some(name) => match file.profiles.get(&name) {
+                Some(profile) => profile.clone(),
+                None => return Err(ConfigError::UnknownProfile(name)),
+            },
+            None => Profile::default(),
+        };
+        let default = Self::default();
+        Ok(Self {
+            addr: cli.addr.or(profile.addr).unwrap_or(default.addr),
+            name: cli.name.or(profile.name),
+            room: cli.room.or(profile.room),
+            log_file: cli.log_file.or(profile.log_file),
+            tls: cli.tls || profile.tls,
+            ca_cert: cli.ca_cert.or(profile.ca_cert),
+            pin_cert: cli.pin_cert.or(profile.pin_cert),
+            server_name: cli.server_name.or(profile.server_name),
+        })
+    }
+
+    // Có CA hoặc chứng chỉ ghim thì cũng là dùng TLS
+    pub fn tls_options(&self) -> Option<TlsOptions> {
+        (self.tls || self.ca_cert.is_some() || self.pin_cert.is_some()).then(|| TlsOptions {
+            ca_cert: self.ca_cert.clone(),
+            pin_cert: self.pin_cert.clone(),
+        })
+    }
+
+    // Các lệnh tự gửi ngay sau khi kết nối
+    pub fn startup_frames(&self) -> Vec<ClientFrame> {
+        let mut frames = Vec::new();
+        if let Some(name) = &self.name {
+            frames.push(ClientFrame::Rename { name: name.clone() });
+        }
+        if let Some(room) = &self.room {
+            frames.push(ClientFrame::Join { room: room.clone(), key: None });
+        }
+        frames
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::Sidebar;
+
+// Số dòng tối đa giữ trong lịch sử
+pub const HISTORY_MAX: usize = 500;
+
+// ~/.local/share/tokio-chat/history, theo XDG_DATA_HOME nếu có
+pub fn default_history_path() -> Option<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(data_dir.join("tokio-chat").join("history"))
+}
+
+// Các dòng đã gửi, Up/Down để gọi lại. Có `path` thì mỗi
+// dòng mới được ghi thêm vào file để dùng ở lần chạy sau
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    // Đang xem dòng thứ mấy, None là đang gõ dòng mới
+    pos: Option<usize>,
+    // Dòng đang gõ dở trước khi bấm Up
+    draft: String,
+}
+
+impl History {
+    // Đọc lịch sử từ file, file chưa có thì bắt đầu trống
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(contents) => contents.lines().filter(|line| !line.is_empty()).map(str::to_owned).collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let mut history = Self { entries, path: Some(path.to_owned()), ..Self::default() };
+        // File dài quá thì ghi lại cho gọn
+        if history.entries.len() > HISTORY_MAX {
+            history.trim();
+            history.save()?;
+        }
+        Ok(history)
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // Thêm dòng vừa gửi, bỏ qua dòng trùng dòng trước
+    // và các lệnh có mật khẩu
+    pub fn push(&mut self, line: &str) -> std::io::Result<()> {
+        self.pos = None;
+        self.draft.clear();
+        if line.trim().is_empty() || is_secret(line) || self.entries.last().is_some_and(|last| last == line) {
+            return Ok(());
+        }
+        self.entries.push(line.to_owned());
+        if self.entries.len() > HISTORY_MAX {
+            self.trim();
+            return self.save();
+        }
+        match &self.path {
+            Some(path) => {
+                create_parent(path)?;
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{line}")
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Dòng cũ hơn, `current` là nội dung ô nhập lúc bắt đầu lùi
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let pos = match self.pos {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_owned();
+                self.entries.len() - 1
+            }
+            Some(pos) => pos.saturating_sub(1),
+        };
+        self.pos = Some(pos);
+        Some(&self.entries[pos])
+    }
+
+    // Dòng mới hơn, qua dòng mới nhất thì trả lại dòng đang gõ dở
+    pub fn newer(&mut self) -> Option<&str> {
+        let pos = self.pos? + 1;
+        if pos < self.entries.len() {
+            self.pos = Some(pos);
+            return Some(&self.entries[pos]);
+        }
+        self.pos = None;
+        Some(&self.draft)
+    }
+
+    fn trim(&mut self) {
+        let extra = self.entries.len().saturating_sub(HISTORY_MAX);
+        self.entries.drain(..extra);
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        create_parent(path)?;
+        let mut contents = self.entries.join("\n");
+        contents.push('\n');
+        std::fs::write(path, contents)
+    }
+}
+
+fn create_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+}
+
+// Không lưu mật khẩu xuống đĩa
+fn is_secret(line: &str) -> bool {
+    let command = line.split_ascii_whitespace().next().unwrap_or_default();
+    matches!(command, "/login" | "/register")
+}
+
+// Tab để hoàn thành lệnh, tên user trong phòng, tên phòng sau /join.
+// Bấm Tab tiếp thì lần lượt qua các lựa chọn khác
+#[derive(Debug, Default)]
+pub struct Completer {
+    // Các lệnh lấy từ trang help của server
+    commands: Vec<String>,
+    cycle: Option<Cycle>,
+}
+
+#[derive(Debug)]
+struct Cycle {
+    // Phần đứng trước từ đang hoàn thành
+    base: String,
+    candidates: Vec<String>,
+    index: usize,
+    // Kết quả lần Tab trước, ô nhập còn y nguyên thì Tab tiếp
+    line: String,
+}
+
+impl Completer {
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    // Lấy tên lệnh từ các dòng help dạng "/op, /deop {user} - ..."
+    pub fn learn_commands(&mut self, help: &str) {
+        for line in help.lines().map(str::trim_start).filter(|line| line.starts_with('/')) {
+            let usage = line.split(" - ").next().unwrap_or_default();
+            for part in usage.split(", ") {
+                let command = part.split_ascii_whitespace().next().unwrap_or_default();
+                if command.len() > 1 && !self.commands.iter().any(|known| known == command) {
+                    self.commands.push(command.to_owned());
+                }
+            }
+        }
+    }
+
+    // Dòng sau khi hoàn thành từ cuối cùng của `line`,
+    // None nếu không có gì để hoàn thành
+    pub fn complete(&mut self, line: &str, sidebar: &Sidebar) -> Option<String> {
+        if let Some(cycle) = self.cycle.as_mut().filter(|cycle| cycle.line == line) {
+            cycle.index = (cycle.index + 1) % cycle.candidates.len();
+            cycle.line = format!("{}{} ", cycle.base, cycle.candidates[cycle.index]);
+            return Some(cycle.line.clone());
+        }
+        self.cycle = None;
+
+        let start = line.trim_end_matches(|c: char| !c.is_whitespace()).len();
+        let (base, word) = line.split_at(start);
+        let options: Vec<String> = if start == 0 && word.starts_with('/') {
+            self.commands.clone()
+        } else if base.trim_end() == "/join" {
+            sidebar.rooms().iter().map(|room| room.name.clone()).collect()
+        } else if word.is_empty() {
+            return None;
+        } else {
+            // @tên thì giữ lại @
+            let at = if word.starts_with('@') { "@" } else { "" };
+            sidebar.users().iter().map(|user| format!("{at}{}", user.name)).collect()
+        };
+
+        let word_lower = word.to_lowercase();
+        let mut candidates: Vec<String> = options
+            .into_iter()
+            .filter(|option| option.to_lowercase().starts_with(&word_lower))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        let first = candidates.first()?;
+        let cycle = Cycle {
+            base: base.to_owned(),
+            line: format!("{base}{first} "),
+            candidates,
+            index: 0,
+        };
+        let line = cycle.line.clone();
+        self.cycle = Some(cycle);
+        Some(line)
+    }
+}
@@ -0,0 +1,13 @@
+mod config;
+mod input;
+mod reconnect;
+mod scrollback;
+mod sidebar;
+mod tls;
+
+pub use config::{default_config_path, Cli, ClientConfig, ConfigError, ConfigFile, Profile, DEFAULT_ADDR};
+pub use input::{default_history_path, Completer, History, HISTORY_MAX};
+pub use reconnect::{Backoff, Reconnect, RECONNECT_MAX, RECONNECT_MIN};
+pub use scrollback::{find_matches, Scrollback};
+pub use sidebar::{role_prefix, Sidebar};
+pub use tls::{connect, server_name, tls_connector, TlsOptions, Transport};
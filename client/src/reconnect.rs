@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use chat_protocol::ClientFrame;
+
+pub const RECONNECT_MIN: Duration = Duration::from_millis(500);
+pub const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+// Thời gian chờ giữa các lần kết nối lại, gấp đôi sau
+// mỗi lần thất bại cho đến mức tối đa
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self { next: min, max }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(RECONNECT_MIN, RECONNECT_MAX)
+    }
+}
+
+// Những gì cần để quay lại đúng chỗ cũ sau khi kết nối lại:
+// token để server trả lại phiên cũ, hoặc nếu phiên đã hết
+// hạn thì tên, phòng và key của phòng để tự đổi tên, vào phòng lại
+#[derive(Debug, Default)]
+pub struct Reconnect {
+    // Token trong frame Welcome gần nhất
+    token: Option<String>,
+    // Tên, phòng và key lúc mất kết nối, chờ được khôi phục
+    pending: Option<(String, String, Option<String>)>,
+    // Đã gửi Resume, đang chờ server trả lời
+    resuming: bool,
+    // Key đã gửi kèm lệnh vào phòng, chờ server xác nhận
+    joining: HashMap<String, String>,
+    // Key của phòng đang ở
+    key: Option<String>,
+}
+
+impl Reconnect {
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Server gửi Welcome, trùng tên cũ nghĩa là đã khôi phục xong
+    pub fn welcome(&mut self, name: &str, token: Option<&str>) {
+        if let Some(token) = token {
+            self.token = Some(token.to_owned());
+        }
+        if self.pending.as_ref().is_some_and(|(old_name, ..)| old_name == name) {
+            self.pending = None;
+            self.resuming = false;
+        }
+    }
+
+    // Ghi lại key của mỗi lần vào phòng đã gửi đi
+    pub fn sent(&mut self, frame: &ClientFrame) {
+        if let ClientFrame::Join { room, key } = frame {
+            match key {
+                Some(key) => self.joining.insert(room.clone(), key.clone()),
+                None => self.joining.remove(room),
+            };
+        }
+    }
+
+    // Server báo đã vào phòng, nhớ key đã dùng cho lần vào lại
+    pub fn joined(&mut self, room: &str) {
+        self.key = self.joining.remove(room);
+    }
+
+    // Mất kết nối giữa chừng lúc đang khôi phục thì giữ chỗ cũ
+    pub fn disconnected(&mut self, name: &str, room: &str) {
+        self.resuming = false;
+        self.joining.clear();
+        if self.pending.is_none() {
+            self.pending = Some((name.to_owned(), room.to_owned(), self.key.clone()));
+        }
+    }
+
+    // Các frame gửi ngay sau khi kết nối lại
+    pub fn reconnected(&mut self) -> Vec<ClientFrame> {
+        if self.pending.is_none() {
+            return Vec::new();
+        }
+        match self.token.take() {
+            Some(token) => {
+                self.resuming = true;
+                vec![ClientFrame::Resume { token }]
+            }
+            None => self.rejoin(),
+        }
+    }
+
+    // Server báo lỗi, nếu đang chờ Resume thì phiên cũ đã hết hạn
+    pub fn error(&mut self) -> Vec<ClientFrame> {
+        if !self.resuming {
+            return Vec::new();
+        }
+        self.resuming = false;
+        self.rejoin()
+    }
+
+    fn rejoin(&mut self) -> Vec<ClientFrame> {
+        let Some((name, room, key)) = self.pending.take() else {
+            return Vec::new();
+        };
+        let join = ClientFrame::Join { room, key };
+        self.sent(&join);
+        vec![ClientFrame::Rename { name }, join]
+    }
+}
@@ -0,0 +1,96 @@
+use std::ops::Range;
+
+// Vị trí đang xem trong danh sách tin nhắn, tính bằng
+// số dòng đã cuộn lên so với dòng mới nhất
+#[derive(Debug, Default)]
+pub struct Scrollback {
+    offset: usize,
+    // Số tin mới đến trong lúc đang cuộn lên
+    unseen: usize,
+}
+
+impl Scrollback {
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn unseen(&self) -> usize {
+        self.unseen
+    }
+
+    pub fn is_scrolled(&self) -> bool {
+        self.offset > 0
+    }
+
+    pub fn up(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_add(lines);
+    }
+
+    pub fn down(&mut self, lines: usize) {
+        self.scroll_to(self.offset.saturating_sub(lines));
+    }
+
+    // Lên tin cũ nhất, `clamp` sẽ đưa về đúng chỗ lúc vẽ
+    pub fn top(&mut self) {
+        self.offset = usize::MAX;
+    }
+
+    pub fn bottom(&mut self) {
+        self.scroll_to(0);
+    }
+
+    pub fn scroll_to(&mut self, offset: usize) {
+        self.offset = offset;
+        if offset == 0 {
+            self.unseen = 0;
+        }
+    }
+
+    // Có tin mới dài `lines` dòng, đang cuộn lên thì
+    // giữ nguyên chỗ đang xem và đếm tin chưa xem
+    pub fn push(&mut self, lines: usize) {
+        if self.is_scrolled() {
+            self.offset = self.offset.saturating_add(lines);
+            self.unseen += 1;
+        }
+    }
+
+    // Không cuộn quá tin cũ nhất, `total` là số dòng
+    // của tất cả tin nhắn, `height` là chiều cao khung
+    pub fn clamp(&mut self, total: usize, height: usize) {
+        self.scroll_to(self.offset.min(total.saturating_sub(height)));
+    }
+}
+
+// Các chỗ khớp `query` trong `text`, không phân biệt hoa thường
+pub fn find_matches(text: &str, query: &str) -> Vec<Range<usize>> {
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return matches;
+    }
+    let mut start = 0;
+    while let Some(c) = text[start..].chars().next() {
+        match match_len(&text[start..], query) {
+            Some(len) => {
+                matches.push(start..start + len);
+                start += len;
+            }
+            None => start += c.len_utf8(),
+        }
+    }
+    matches
+}
+
+// Độ dài phần đầu của `text` khớp với `query`
+fn match_len(text: &str, query: &str) -> Option<usize> {
+    let mut chars = text.chars();
+    let mut len = 0;
+    for q in query.chars() {
+        let c = chars.next()?;
+        if !c.to_lowercase().eq(q.to_lowercase()) {
+            return None;
+        }
+        len += c.len_utf8();
+    }
+    Some(len)
+}
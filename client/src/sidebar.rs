@@ -0,0 +1,169 @@
+use chat_protocol::{ClientFrame, Role, RoomInfo, ServerFrame, UserInfo};
+
+// Dữ liệu cho hai panel bên cạnh: danh sách phòng bên trái,
+// user trong phòng hiện tại bên phải
+#[derive(Debug)]
+pub struct Sidebar {
+    pub show_rooms: bool,
+    pub show_users: bool,
+    rooms: Vec<RoomInfo>,
+    users: Vec<UserInfo>,
+    // Phòng đang chọn bằng bàn phím
+    selected: usize,
+    // Số lần tự hỏi danh sách phòng, user mà server chưa trả lời,
+    // câu trả lời cho những lần này không hiện ra khung tin nhắn
+    quiet_rooms: usize,
+    quiet_users: usize,
+}
+
+impl Default for Sidebar {
+    fn default() -> Self {
+        Self {
+            show_rooms: true,
+            show_users: true,
+            rooms: Vec::new(),
+            users: Vec::new(),
+            selected: 0,
+            quiet_rooms: 0,
+            quiet_users: 0,
+        }
+    }
+}
+
+impl Sidebar {
+    pub fn rooms(&self) -> &[RoomInfo] {
+        &self.rooms
+    }
+
+    pub fn users(&self) -> &[UserInfo] {
+        &self.users
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_room(&self) -> Option<&str> {
+        self.rooms.get(self.selected).map(|room| room.name.as_str())
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.rooms.len() {
+            self.selected = index;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.select(self.selected + 1);
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    // Các frame để tự làm mới cả hai danh sách
+    pub fn refresh(&mut self) -> [ClientFrame; 2] {
+        self.quiet_rooms += 1;
+        self.quiet_users += 1;
+        [ClientFrame::Rooms, ClientFrame::Users]
+    }
+
+    // Mất kết nối thì các câu hỏi đang chờ cũng mất theo
+    pub fn disconnected(&mut self) {
+        self.quiet_rooms = 0;
+        self.quiet_users = 0;
+    }
+
+    // Cập nhật theo frame từ server, `me` và `room` là tên và phòng
+    // hiện tại. Trả về false nếu frame là câu trả lời cho lần tự
+    // làm mới, không cần hiện ra
+    pub fn apply(&mut self, frame: &ServerFrame, me: &str, room: &str) -> bool {
+        match frame {
+            ServerFrame::Rooms { rooms } => {
+                // Giữ nguyên phòng đang chọn dù thứ tự thay đổi
+                let selected = self.selected_room().map(str::to_owned);
+                self.rooms = rooms.clone();
+                self.selected = selected
+                    .and_then(|selected| self.rooms.iter().position(|room| room.name == selected))
+                    .unwrap_or(0);
+                return !take(&mut self.quiet_rooms);
+            }
+            ServerFrame::Users { room: listed, users } => {
+                // Trả lời muộn cho phòng vừa rời thì bỏ qua
+                if listed == room {
+                    self.users = users.clone();
+                    self.sort_users();
+                }
+                return !take(&mut self.quiet_users);
+            }
+            ServerFrame::Joined { room: joined, user } if joined == room && user != me => {
+                self.users.retain(|info| info.name != *user);
+                self.users.push(UserInfo {
+                    name: user.clone(),
+                    role: Role::Member,
+                    joined: 0,
+                    idle_secs: 0,
+                    away: None,
+                });
+                self.sort_users();
+                self.count(room, 1);
+            }
+            ServerFrame::Left { room: left, user } | ServerFrame::Kicked { room: left, user, .. } if left == room => {
+                let before = self.users.len();
+                self.users.retain(|info| info.name != *user);
+                if self.users.len() < before {
+                    self.count(room, -1);
+                }
+            }
+            ServerFrame::Renamed { from, to } => {
+                for info in self.users.iter_mut().filter(|info| info.name == *from) {
+                    info.name = to.clone();
+                }
+                self.sort_users();
+            }
+            ServerFrame::RoleChanged { room: changed, user, role, .. } if changed == room => {
+                for info in self.users.iter_mut().filter(|info| info.name == *user) {
+                    info.role = *role;
+                }
+                self.sort_users();
+            }
+            // Lỗi không cho biết trả lời câu hỏi nào, thôi không chờ
+            // nữa để câu trả lời sau này không bị giấu nhầm. Lỗi thì
+            // vẫn hiện ra
+            ServerFrame::Error { .. } => {
+                self.quiet_rooms = 0;
+                self.quiet_users = 0;
+            }
+            _ => (),
+        }
+        true
+    }
+
+    // Chủ phòng, operator, voice lên trước, sau đó theo tên
+    fn sort_users(&mut self) {
+        self.users.sort_by(|a, b| b.role.cmp(&a.role).then_with(|| a.name.cmp(&b.name)));
+    }
+
+    // Số user của phòng hiện tại thay đổi theo sự kiện vào, ra
+    fn count(&mut self, room: &str, delta: isize) {
+        if let Some(info) = self.rooms.iter_mut().find(|info| info.name == room) {
+            info.users = info.users.saturating_add_signed(delta);
+        }
+    }
+}
+
+fn take(pending: &mut usize) -> bool {
+    let quiet = *pending > 0;
+    *pending = pending.saturating_sub(1);
+    quiet
+}
+
+// Ký hiệu kiểu IRC trước tên: ~ chủ phòng, @ operator, + voice
+pub fn role_prefix(role: Role) -> &'static str {
+    match role {
+        Role::Member => "",
+        Role::Voice => "+",
+        Role::Operator => "@",
+        Role::Owner => "~",
+    }
+}
@@ -0,0 +1,137 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+// Kết nối tới server, tcp thường hoặc tls
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    // CA tin cậy thêm, ngoài các root CA có sẵn
+    pub ca_cert: Option<PathBuf>,
+    // Chỉ chấp nhận đúng chứng chỉ này, bỏ qua chuỗi CA
+    pub pin_cert: Option<PathBuf>,
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, io::Error> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("can't load {}: {err}", path.display()))
+        })?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificates in {}", path.display()),
+        ));
+    }
+    Ok(certs)
+}
+
+pub fn tls_connector(options: &TlsOptions) -> Result<TlsConnector, io::Error> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+    let config = match &options.pin_cert {
+        Some(path) => {
+            let cert = load_certs(path)?.remove(0);
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCert { cert, provider }))
+                .with_no_client_auth()
+        }
+        None => {
+            let mut roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            if let Some(path) = &options.ca_cert {
+                for cert in load_certs(path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                }
+            }
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+// Lấy host từ địa chỉ "host:port" để xác thực chứng chỉ
+pub fn server_name(addr: &str) -> Result<ServerName<'static>, io::Error> {
+    let host = match addr.rsplit_once(':') {
+        Some((host, _port)) => host,
+        None => addr,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host.to_owned())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+pub async fn connect(
+    addr: &str,
+    tls: Option<(&TlsConnector, ServerName<'static>)>,
+) -> Result<Box<dyn Transport>, io::Error> {
+    let tcp = TcpStream::connect(addr).await?;
+    match tls {
+        Some((connector, server_name)) => Ok(Box::new(connector.connect(server_name, tcp).await?)),
+        None => Ok(Box::new(tcp)),
+    }
+}
+
+// Ghim chứng chỉ: chỉ so sánh đúng chứng chỉ của server,
+// chữ ký handshake vẫn được kiểm tra như bình thường
+#[derive(Debug)]
+struct PinnedCert {
+    cert: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("server certificate does not match the pinned certificate".into()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
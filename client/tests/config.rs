@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+use chat_client::{Cli, ClientConfig, ConfigError, DEFAULT_ADDR};
+use chat_protocol::ClientFrame;
+use clap::Parser;
+
+fn write_config(file_name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-client-{file_name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+const PROFILES: &str = r#"
+default_profile = "staging"
+
+[profiles.staging]
+addr = "staging.example.com:8443"
+tls = true
+room = "ops"
+
+[profiles.production]
+addr = "chat.example.com:8443"
+name = "alice"
+pin_cert = "prod.pem"
+"#;
+
+fn config(file_name: &str, args: &[&str]) -> Result<ClientConfig, ConfigError> {
+    let path = write_config(file_name, PROFILES);
+    let mut argv = vec!["chat-client", "--config", path.to_str().unwrap()];
+    argv.extend(args);
+    let config = ClientConfig::from_cli(Cli::try_parse_from(argv).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    config
+}
+
+#[test]
+fn default_profile_is_used_without_flags() {
+    let config = config("default.toml", &[]).unwrap();
+    assert_eq!(config.addr, "staging.example.com:8443");
+    assert_eq!(config.room.as_deref(), Some("ops"));
+    assert_eq!(config.name, None);
+    assert!(config.tls_options().is_some());
+    assert_eq!(config.startup_frames(), [ClientFrame::Join { room: "ops".into(), key: None }]);
+}
+
+#[test]
+fn flags_override_the_chosen_profile() {
+    let config = config("override.toml", &["--profile", "production", "--name", "bob", "-r", "rust"]).unwrap();
+    assert_eq!(config.addr, "chat.example.com:8443");
+    assert_eq!(config.pin_cert, Some(PathBuf::from("prod.pem")));
+    // a pinned cert means tls even without tls = true
+    assert!(config.tls_options().is_some());
+    assert_eq!(
+        config.startup_frames(),
+        [ClientFrame::Rename { name: "bob".into() }, ClientFrame::Join { room: "rust".into(), key: None }],
+    );
+}
+
+#[test]
+fn unknown_profiles_and_fields_are_errors() {
+    let err = config("unknown.toml", &["--profile", "dev"]).unwrap_err();
+    assert_eq!(err.to_string(), "no profile named dev in the config file");
+
+    let path = write_config("typo.toml", "[profiles.local]\nadress = \"127.0.0.1:8080\"\n");
+    let cli = Cli::try_parse_from(["chat-client", "--config", path.to_str().unwrap()]).unwrap();
+    assert!(matches!(ClientConfig::from_cli(cli), Err(ConfigError::Parse(..))));
+    std::fs::remove_file(&path).unwrap();
+
+    let cli = Cli::try_parse_from(["chat-client", "--config", "/nonexistent/client.toml"]).unwrap();
+    assert!(matches!(ClientConfig::from_cli(cli), Err(ConfigError::Read(..))));
+}
+
+#[test]
+fn example_config_parses() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("client.example.toml");
+    let cli = Cli::try_parse_from(["chat-client", "--config", path.to_str().unwrap()]).unwrap();
+    let config = ClientConfig::from_cli(cli).unwrap();
+    assert_eq!(config.addr, DEFAULT_ADDR);
+    assert!(config.tls_options().is_none());
+    assert!(config.startup_frames().is_empty());
+}
@@ -0,0 +1,84 @@
+use chat_client::{Completer, History, Sidebar, HISTORY_MAX};
+use chat_protocol::{Role, RoomInfo, RoomModes, ServerFrame, UserInfo};
+
+fn sidebar() -> Sidebar {
+    let room = |name: &str| RoomInfo {
+        name: name.into(),
+        users: 1,
+        topic: None,
+        creator: "alice".into(),
+        created: 0,
+        modes: RoomModes::default(),
+    };
+    let user = |name: &str| UserInfo { name: name.into(), role: Role::Member, joined: 0, idle_secs: 0, away: None };
+    let mut sidebar = Sidebar::default();
+    sidebar.apply(&ServerFrame::Rooms { rooms: vec![room("main"), room("rust"), room("ruby")] }, "alice", "main");
+    sidebar.apply(&ServerFrame::Users { room: "main".into(), users: vec![user("alice"), user("albert"), user("bob")] }, "alice", "main");
+    sidebar
+}
+
+#[test]
+fn history_recalls_and_persists() {
+    let dir = std::env::temp_dir().join(format!("chat-history-{}", std::process::id()));
+    let path = dir.join("history");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut history = History::load(&path).unwrap();
+    assert_eq!(history.older("draft"), None);
+    history.push("hello").unwrap();
+    history.push("hello").unwrap();
+    history.push("/login alice secret").unwrap();
+    history.push("/join rust").unwrap();
+    assert_eq!(history.entries(), ["hello", "/join rust"]);
+
+    assert_eq!(history.older("typing"), Some("/join rust"));
+    assert_eq!(history.older("ignored"), Some("hello"));
+    assert_eq!(history.older("ignored"), Some("hello"));
+    assert_eq!(history.newer(), Some("/join rust"));
+    // past the newest line the unsent draft comes back
+    assert_eq!(history.newer(), Some("typing"));
+    assert_eq!(history.newer(), None);
+
+    let history = History::load(&path).unwrap();
+    assert_eq!(history.entries(), ["hello", "/join rust"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_keeps_the_newest_lines() {
+    let mut history = History::default();
+    for i in 0..HISTORY_MAX + 10 {
+        history.push(&i.to_string()).unwrap();
+    }
+    assert_eq!(history.entries().len(), HISTORY_MAX);
+    assert_eq!(history.entries()[0], "10");
+}
+
+#[test]
+fn commands_come_from_the_help_text() {
+    let mut completer = Completer::default();
+    completer.learn_commands("Server commands\n  /help - print this message\n  /op, /deop {user} - grant or take operator");
+    completer.learn_commands("  /join {room} [key] - joins room");
+    completer.learn_commands("Messages can use *bold*");
+    assert_eq!(completer.commands(), ["/help", "/op", "/deop", "/join"]);
+}
+
+#[test]
+fn tab_completes_by_context() {
+    let sidebar = sidebar();
+    let mut completer = Completer::default();
+    completer.learn_commands("  /join {room} - joins room\n  /help - help\n  /history [n] - history");
+
+    assert_eq!(completer.complete("/j", &sidebar).as_deref(), Some("/join "));
+    assert_eq!(completer.complete("/join ru", &sidebar).as_deref(), Some("/join ruby "));
+    // tabbing again cycles through the other matches
+    assert_eq!(completer.complete("/join ruby ", &sidebar).as_deref(), Some("/join rust "));
+    assert_eq!(completer.complete("/join rust ", &sidebar).as_deref(), Some("/join ruby "));
+    assert_eq!(completer.complete("/join ", &sidebar).as_deref(), Some("/join main "));
+
+    assert_eq!(completer.complete("hi AL", &sidebar).as_deref(), Some("hi albert "));
+    assert_eq!(completer.complete("hi albert ", &sidebar).as_deref(), Some("hi alice "));
+    assert_eq!(completer.complete("thanks @b", &sidebar).as_deref(), Some("thanks @bob "));
+    assert_eq!(completer.complete("hi ", &sidebar), None);
+    assert_eq!(completer.complete("/x", &sidebar), None);
+}
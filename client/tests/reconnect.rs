@@ -0,0 +1,96 @@
+use std::time::Duration;
+use chat_client::{Backoff, Reconnect, RECONNECT_MAX, RECONNECT_MIN};
+use chat_protocol::ClientFrame;
+
+#[test]
+fn backoff_doubles_up_to_the_max() {
+    let mut backoff = Backoff::default();
+    assert_eq!(backoff.next_delay(), RECONNECT_MIN);
+    assert_eq!(backoff.next_delay(), RECONNECT_MIN * 2);
+    assert_eq!(backoff.next_delay(), RECONNECT_MIN * 4);
+    for _ in 0..20 {
+        backoff.next_delay();
+    }
+    assert_eq!(backoff.next_delay(), RECONNECT_MAX);
+
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(3));
+    let delays: Vec<_> = (0..4).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, [1, 2, 3, 3]);
+}
+
+#[test]
+fn resumes_with_the_latest_token() {
+    let mut reconnect = Reconnect::default();
+    assert!(reconnect.reconnected().is_empty());
+
+    reconnect.welcome("alice", Some("t1"));
+    reconnect.disconnected("alice", "rust");
+    assert!(reconnect.is_pending());
+    assert_eq!(reconnect.reconnected(), [ClientFrame::Resume { token: "t1".into() }]);
+    // the fresh session's welcome comes first
+    reconnect.welcome("guest1", Some("t2"));
+    assert!(reconnect.is_pending());
+    reconnect.welcome("alice", Some("t2"));
+    assert!(!reconnect.is_pending());
+    assert!(reconnect.error().is_empty());
+
+    reconnect.disconnected("alice", "rust");
+    assert_eq!(reconnect.reconnected(), [ClientFrame::Resume { token: "t2".into() }]);
+}
+
+#[test]
+fn expired_sessions_rejoin_by_name_and_room() {
+    let mut reconnect = Reconnect::default();
+    reconnect.welcome("alice", Some("t1"));
+    reconnect.disconnected("alice", "rust");
+    reconnect.reconnected();
+    reconnect.welcome("guest1", Some("t2"));
+    let rejoin = [
+        ClientFrame::Rename { name: "alice".into() },
+        ClientFrame::Join { room: "rust".into(), key: None },
+    ];
+    assert_eq!(reconnect.error(), rejoin);
+    assert!(!reconnect.is_pending());
+
+    // dropped again before the resume went through, and
+    // the token is spent, so go by name and room instead
+    reconnect.disconnected("alice", "rust");
+    assert_eq!(reconnect.reconnected(), [ClientFrame::Resume { token: "t2".into() }]);
+    reconnect.disconnected("guest2", "main");
+    assert_eq!(reconnect.reconnected(), rejoin);
+}
+
+#[test]
+fn keyed_rooms_rejoin_with_their_key() {
+    let mut reconnect = Reconnect::default();
+    reconnect.welcome("alice", Some("t1"));
+    reconnect.sent(&ClientFrame::Join { room: "incident".into(), key: Some("hunter2".into()) });
+    reconnect.joined("incident");
+    reconnect.disconnected("alice", "incident");
+    reconnect.reconnected();
+    reconnect.welcome("guest1", Some("t2"));
+    let rejoin = [
+        ClientFrame::Rename { name: "alice".into() },
+        ClientFrame::Join { room: "incident".into(), key: Some("hunter2".into()) },
+    ];
+    assert_eq!(reconnect.error(), rejoin);
+
+    // the rejoin itself counts, so the key sticks around
+    reconnect.joined("incident");
+    reconnect.disconnected("alice", "incident");
+    reconnect.reconnected();
+    reconnect.welcome("guest2", Some("t3"));
+    assert_eq!(reconnect.error(), rejoin);
+
+    // a failed keyed join doesn't leak into the next room
+    reconnect.sent(&ClientFrame::Join { room: "ops".into(), key: Some("wrong".into()) });
+    reconnect.sent(&ClientFrame::Join { room: "main".into(), key: None });
+    reconnect.joined("main");
+    reconnect.disconnected("alice", "main");
+    reconnect.reconnected();
+    reconnect.welcome("guest3", None);
+    assert_eq!(
+        reconnect.error(),
+        [ClientFrame::Rename { name: "alice".into() }, ClientFrame::Join { room: "main".into(), key: None }],
+    );
+}
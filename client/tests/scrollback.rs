@@ -0,0 +1,44 @@
+use chat_client::{find_matches, Scrollback};
+
+#[test]
+fn scrolling_stays_in_bounds() {
+    let mut scrollback = Scrollback::default();
+    scrollback.up(10);
+    scrollback.clamp(25, 20);
+    assert_eq!(scrollback.offset(), 5);
+    scrollback.top();
+    scrollback.clamp(100, 20);
+    assert_eq!(scrollback.offset(), 80);
+    scrollback.down(100);
+    assert!(!scrollback.is_scrolled());
+
+    // everything fits, nothing to scroll
+    scrollback.up(3);
+    scrollback.clamp(10, 20);
+    assert_eq!(scrollback.offset(), 0);
+}
+
+#[test]
+fn new_messages_keep_the_view_while_scrolled() {
+    let mut scrollback = Scrollback::default();
+    scrollback.push(2);
+    assert_eq!((scrollback.offset(), scrollback.unseen()), (0, 0));
+
+    scrollback.up(5);
+    scrollback.push(2);
+    scrollback.push(1);
+    assert_eq!((scrollback.offset(), scrollback.unseen()), (8, 2));
+    scrollback.down(3);
+    assert_eq!(scrollback.unseen(), 2);
+    scrollback.bottom();
+    assert_eq!((scrollback.offset(), scrollback.unseen()), (0, 0));
+}
+
+#[test]
+fn search_ignores_case() {
+    assert_eq!(find_matches("Rust is rusty", "rust"), [0..4, 8..12]);
+    assert_eq!(find_matches("aaaaa", "aa"), [0..2, 2..4]);
+    assert_eq!(find_matches("Xin chào CHÀO", "chào"), [4..9, 10..15]);
+    assert!(find_matches("hello", "").is_empty());
+    assert!(find_matches("hi", "hello").is_empty());
+}
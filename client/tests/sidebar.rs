@@ -0,0 +1,94 @@
+use chat_client::Sidebar;
+use chat_protocol::{ClientFrame, Role, RoomInfo, RoomModes, ServerFrame, UserInfo};
+
+fn room(name: &str, users: usize) -> RoomInfo {
+    RoomInfo {
+        name: name.into(),
+        users,
+        topic: None,
+        creator: "alice".into(),
+        created: 0,
+        modes: RoomModes::default(),
+    }
+}
+
+fn user(name: &str, role: Role) -> UserInfo {
+    UserInfo { name: name.into(), role, joined: 0, idle_secs: 0, away: None }
+}
+
+fn names(sidebar: &Sidebar) -> Vec<&str> {
+    sidebar.users().iter().map(|user| user.name.as_str()).collect()
+}
+
+#[test]
+fn refreshes_are_answered_quietly() {
+    let mut sidebar = Sidebar::default();
+    assert_eq!(sidebar.refresh(), [ClientFrame::Rooms, ClientFrame::Users]);
+    let rooms = ServerFrame::Rooms { rooms: vec![room("main", 2), room("rust", 1)] };
+    let users = ServerFrame::Users { room: "main".into(), users: vec![user("bob", Role::Member), user("alice", Role::Owner)] };
+    assert!(!sidebar.apply(&rooms, "alice", "main"));
+    assert!(!sidebar.apply(&users, "alice", "main"));
+    assert_eq!(names(&sidebar), ["alice", "bob"]);
+    assert_eq!(sidebar.rooms().len(), 2);
+
+    // asked for by the user, so shown too
+    assert!(sidebar.apply(&rooms, "alice", "main"));
+
+    // answers lost with the connection aren't waited for
+    sidebar.refresh();
+    sidebar.disconnected();
+    assert!(sidebar.apply(&rooms, "alice", "main"));
+}
+
+#[test]
+fn errors_dont_leave_refreshes_pending() {
+    let mut sidebar = Sidebar::default();
+    sidebar.refresh();
+    // the refresh got turned away, e.g. by the rate limiter
+    assert!(sidebar.apply(&ServerFrame::error("Slow down"), "alice", "main"));
+
+    // so the next lists the user asks for are shown
+    let rooms = ServerFrame::Rooms { rooms: vec![room("main", 1)] };
+    let users = ServerFrame::Users { room: "main".into(), users: vec![user("alice", Role::Owner)] };
+    assert!(sidebar.apply(&rooms, "alice", "main"));
+    assert!(sidebar.apply(&users, "alice", "main"));
+}
+
+#[test]
+fn room_events_update_the_lists() {
+    let mut sidebar = Sidebar::default();
+    sidebar.apply(&ServerFrame::Rooms { rooms: vec![room("main", 1)] }, "alice", "main");
+    sidebar.apply(&ServerFrame::Users { room: "main".into(), users: vec![user("alice", Role::Member)] }, "alice", "main");
+
+    sidebar.apply(&ServerFrame::Joined { room: "main".into(), user: "carol".into() }, "alice", "main");
+    sidebar.apply(&ServerFrame::Joined { room: "main".into(), user: "bob".into() }, "alice", "main");
+    assert_eq!(names(&sidebar), ["alice", "bob", "carol"]);
+    assert_eq!(sidebar.rooms()[0].users, 3);
+
+    let op = ServerFrame::RoleChanged { room: "main".into(), user: "carol".into(), role: Role::Operator, by: "alice".into() };
+    sidebar.apply(&op, "alice", "main");
+    sidebar.apply(&ServerFrame::Renamed { from: "bob".into(), to: "bobby".into() }, "alice", "main");
+    assert_eq!(names(&sidebar), ["carol", "alice", "bobby"]);
+
+    sidebar.apply(&ServerFrame::Left { room: "main".into(), user: "bobby".into() }, "alice", "main");
+    let kicked = ServerFrame::Kicked { room: "main".into(), user: "carol".into(), by: "alice".into(), reason: None };
+    sidebar.apply(&kicked, "alice", "main");
+    assert_eq!(names(&sidebar), ["alice"]);
+    assert_eq!(sidebar.rooms()[0].users, 1);
+}
+
+#[test]
+fn selection_follows_the_room() {
+    let mut sidebar = Sidebar::default();
+    assert_eq!(sidebar.selected_room(), None);
+    sidebar.apply(&ServerFrame::Rooms { rooms: vec![room("main", 3), room("rust", 2)] }, "alice", "main");
+    sidebar.select_prev();
+    assert_eq!(sidebar.selected_room(), Some("main"));
+    sidebar.select_next();
+    sidebar.select_next();
+    assert_eq!(sidebar.selected_room(), Some("rust"));
+
+    // rust got busier and moved up, still selected
+    sidebar.apply(&ServerFrame::Rooms { rooms: vec![room("rust", 4), room("main", 3)] }, "alice", "main");
+    assert_eq!((sidebar.selected(), sidebar.selected_room()), (0, Some("rust")));
+}
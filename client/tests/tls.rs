@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use chat_client::{connect, server_name, tls_connector, TlsOptions};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+struct TestPki {
+    ca_path: PathBuf,
+    leaf_path: PathBuf,
+    other_path: PathBuf,
+    acceptor: TlsAcceptor,
+}
+
+// the pem files go away with the test
+impl Drop for TestPki {
+    fn drop(&mut self) {
+        for path in [&self.ca_path, &self.leaf_path, &self.other_path] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn write(name: &str, pem: String) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-client-{name}.pem", std::process::id()));
+    std::fs::write(&path, pem).unwrap();
+    path
+}
+
+// a CA, a leaf for "localhost" signed by it that the
+// test server uses, and an unrelated self-signed cert
+fn test_pki(name: &str) -> TestPki {
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+    let leaf_key = KeyPair::generate().unwrap();
+    let leaf = CertificateParams::new(vec!["localhost".to_owned()])
+        .unwrap()
+        .signed_by(&leaf_key, &ca)
+        .unwrap();
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+
+    let key = PrivateKeyDer::try_from(leaf_key.serialize_der()).unwrap();
+    let chain: Vec<CertificateDer<'static>> = vec![leaf.der().clone()];
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+
+    TestPki {
+        ca_path: write(&format!("{name}-ca"), ca.pem()),
+        leaf_path: write(&format!("{name}-leaf"), leaf.pem()),
+        other_path: write(&format!("{name}-other"), other.cert.pem()),
+        acceptor: TlsAcceptor::from(Arc::new(config)),
+    }
+}
+
+// echoes back a single line worth of bytes over tls
+async fn spawn_echo(acceptor: TlsAcceptor) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((tcp, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                if let Ok(mut tls) = acceptor.accept(tcp).await {
+                    let mut buf = [0; 64];
+                    let n = tls.read(&mut buf).await.unwrap_or(0);
+                    let _ = tls.write_all(&buf[..n]).await;
+                    let _ = tls.shutdown().await;
+                }
+            });
+        }
+    });
+    format!("localhost:{}", addr.port())
+}
+
+async fn round_trip(addr: &str, options: TlsOptions) -> std::io::Result<Vec<u8>> {
+    let connector = tls_connector(&options)?;
+    let mut conn = connect(addr, Some((&connector, server_name(addr)?))).await?;
+    conn.write_all(b"ping\n").await?;
+    let mut buf = Vec::new();
+    conn.read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+#[tokio::test]
+async fn trusts_custom_ca() {
+    let pki = test_pki("ca");
+    let addr = spawn_echo(pki.acceptor.clone()).await;
+    let options = TlsOptions { ca_cert: Some(pki.ca_path.clone()), pin_cert: None };
+    assert_eq!(round_trip(&addr, options).await.unwrap(), b"ping\n");
+}
+
+#[tokio::test]
+async fn rejects_unknown_ca() {
+    let pki = test_pki("unknown");
+    let addr = spawn_echo(pki.acceptor.clone()).await;
+    assert!(round_trip(&addr, TlsOptions::default()).await.is_err());
+}
+
+#[tokio::test]
+async fn pinned_cert_must_match() {
+    let pki = test_pki("pin");
+    let addr = spawn_echo(pki.acceptor.clone()).await;
+    let pinned = TlsOptions { ca_cert: None, pin_cert: Some(pki.leaf_path.clone()) };
+    assert_eq!(round_trip(&addr, pinned).await.unwrap(), b"ping\n");
+    let wrong = TlsOptions { ca_cert: None, pin_cert: Some(pki.other_path.clone()) };
+    assert!(round_trip(&addr, wrong).await.is_err());
+}
+
+#[test]
+fn server_name_from_address() {
+    assert!(matches!(server_name("localhost:8080").unwrap(), rustls::pki_types::ServerName::DnsName(_)));
+    assert!(matches!(server_name("127.0.0.1:8080").unwrap(), rustls::pki_types::ServerName::IpAddress(_)));
+    assert!(matches!(server_name("[::1]:8080").unwrap(), rustls::pki_types::ServerName::IpAddress(_)));
+}
@@ -0,0 +1,587 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+pub mod markup;
+
+pub use markup::Span;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Text => "text",
+            Format::Json => "json",
+        }
+    }
+}
+
+// per room, ordered from least to most privileged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Member,
+    Voice,
+    Operator,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Member => "member",
+            Role::Voice => "voice",
+            Role::Operator => "operator",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoomModes {
+    pub invite_only: bool,
+    // the key itself is never sent to clients
+    pub keyed: bool,
+    pub moderated: bool,
+    // hidden from /rooms for everyone outside the room
+    pub secret: bool,
+    pub max_users: Option<usize>,
+    // seconds members have to wait between messages
+    pub slow_secs: Option<u64>,
+}
+
+impl RoomModes {
+    // irc style, flags first and then their
+    // values in the same order, like "+ml 20"
+    pub fn flags(&self) -> String {
+        let mut flags = String::from("+");
+        let flags_set = [(self.invite_only, 'i'), (self.keyed, 'k'), (self.moderated, 'm'), (self.secret, 's')];
+        for (set, flag) in flags_set {
+            if set {
+                flags.push(flag);
+            }
+        }
+        let mut values = Vec::new();
+        if let Some(max_users) = self.max_users {
+            flags.push('l');
+            values.push(max_users.to_string());
+        }
+        if let Some(slow_secs) = self.slow_secs {
+            flags.push('d');
+            values.push(slow_secs.to_string());
+        }
+        if flags.len() == 1 {
+            return String::new();
+        }
+        for value in values {
+            flags.push(' ');
+            flags.push_str(&value);
+        }
+        flags
+    }
+
+    pub fn apply(&mut self, change: ModeChange) {
+        match change {
+            ModeChange::InviteOnly(set) => self.invite_only = set,
+            ModeChange::Key(key) => self.keyed = key.is_some(),
+            ModeChange::Moderated(set) => self.moderated = set,
+            ModeChange::Secret(set) => self.secret = set,
+            ModeChange::MaxUsers(max_users) => self.max_users = max_users,
+            ModeChange::SlowMode(slow_secs) => self.slow_secs = slow_secs,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModeChange {
+    InviteOnly(bool),
+    Key(Option<String>),
+    Moderated(bool),
+    Secret(bool),
+    MaxUsers(Option<usize>),
+    SlowMode(Option<u64>),
+}
+
+// everything a client can ask of the server, plain
+// text clients send these as lines which get parsed
+// with `ClientFrame::from_line`, json clients send
+// them as serialized frames
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Chat { text: String },
+    // "/me waves", shown as "* alice waves"
+    Action { text: String },
+    Direct { to: String, text: String },
+    Help,
+    Rename { name: String },
+    Register { password: String },
+    Login { name: String, password: String },
+    Join {
+        room: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    Rooms,
+    Users,
+    History { count: Option<usize> },
+    Quit,
+    Protocol { format: Format, version: u32 },
+    SetRole { user: String, role: Role },
+    Kick { user: String, reason: Option<String> },
+    // a user name or an ip address
+    Ban { target: String },
+    Unban { target: String },
+    // shows the topic when there's none given
+    Topic { topic: Option<String> },
+    Mode { change: Option<ModeChange> },
+    Invite { user: String },
+    Uninvite { user: String },
+    // cleared by whatever the user sends next
+    Away { message: Option<String> },
+    Whois { user: String },
+    // picks up a session that lost its connection, with
+    // the token from the last `ServerFrame::Welcome`
+    Resume { token: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand(String),
+    UnknownFormat(String),
+    InvalidVersion(String),
+    InvalidCount(String),
+    InvalidMode(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownCommand(command) => write!(f, "Unrecognized command {command}, try /help"),
+            Self::UnknownFormat(format) => write!(f, "Unknown protocol {format}, try text or json"),
+            Self::InvalidVersion(version) => write!(f, "Invalid protocol version {version}"),
+            Self::InvalidCount(count) => write!(f, "Invalid message count {count}"),
+            Self::InvalidMode(mode) => write!(f, "Invalid mode {mode}, try +i, +k {{key}}, +m, +s, +l {{n}} or +d {{secs}}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ClientFrame {
+    // same as the json tag, handy for logs and metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Chat { .. } => "chat",
+            Self::Action { .. } => "action",
+            Self::Direct { .. } => "direct",
+            Self::Help => "help",
+            Self::Rename { .. } => "rename",
+            Self::Register { .. } => "register",
+            Self::Login { .. } => "login",
+            Self::Join { .. } => "join",
+            Self::Rooms => "rooms",
+            Self::Users => "users",
+            Self::History { .. } => "history",
+            Self::Quit => "quit",
+            Self::Protocol { .. } => "protocol",
+            Self::SetRole { .. } => "set_role",
+            Self::Kick { .. } => "kick",
+            Self::Ban { .. } => "ban",
+            Self::Unban { .. } => "unban",
+            Self::Topic { .. } => "topic",
+            Self::Mode { .. } => "mode",
+            Self::Invite { .. } => "invite",
+            Self::Uninvite { .. } => "uninvite",
+            Self::Away { .. } => "away",
+            Self::Whois { .. } => "whois",
+            Self::Resume { .. } => "resume",
+        }
+    }
+
+    pub fn from_line(line: &str) -> Result<Self, ParseError> {
+        if !line.starts_with('/') {
+            return Ok(Self::Chat { text: line.to_owned() });
+        }
+        let mut args = line.split_ascii_whitespace();
+        // a lone "/" still counts as a command
+        let command = args.next().unwrap_or("/");
+        let mut arg = || args.next().unwrap_or_default().to_owned();
+        let frame = match command {
+            "/help" => Self::Help,
+            "/me" => Self::Action { text: line[command.len()..].trim().to_owned() },
+            "/name" => Self::Rename { name: arg() },
+            "/join" => {
+                let room = arg();
+                let key = arg();
+                Self::Join { room, key: (!key.is_empty()).then_some(key) }
+            }
+            "/invite" => Self::Invite { user: arg() },
+            "/uninvite" => Self::Uninvite { user: arg() },
+            "/away" => {
+                let message = line[command.len()..].trim();
+                Self::Away { message: (!message.is_empty()).then(|| message.to_owned()) }
+            }
+            "/whois" => Self::Whois { user: arg() },
+            "/resume" => Self::Resume { token: arg() },
+            "/rooms" => Self::Rooms,
+            "/users" => Self::Users,
+            "/history" => {
+                let count = match arg().as_str() {
+                    "" => None,
+                    count => Some(
+                        count
+                            .parse()
+                            .map_err(|_| ParseError::InvalidCount(count.to_owned()))?,
+                    ),
+                };
+                Self::History { count }
+            }
+            "/quit" => Self::Quit,
+            "/msg" => {
+                // keep the message text as typed, only
+                // the recipient is split off
+                let (to, text) = split_rest(&line[command.len()..]);
+                Self::Direct { to: to.to_owned(), text: text.to_owned() }
+            }
+            "/register" => Self::Register { password: line[command.len()..].trim().to_owned() },
+            "/login" => {
+                let (name, password) = split_rest(&line[command.len()..]);
+                Self::Login { name: name.to_owned(), password: password.trim_end().to_owned() }
+            }
+            "/op" => Self::SetRole { user: arg(), role: Role::Operator },
+            "/voice" => Self::SetRole { user: arg(), role: Role::Voice },
+            "/deop" | "/devoice" => Self::SetRole { user: arg(), role: Role::Member },
+            "/kick" => {
+                let (user, reason) = split_rest(&line[command.len()..]);
+                let reason = reason.trim_end();
+                Self::Kick {
+                    user: user.to_owned(),
+                    reason: (!reason.is_empty()).then(|| reason.to_owned()),
+                }
+            }
+            "/ban" => Self::Ban { target: arg() },
+            "/unban" => Self::Unban { target: arg() },
+            "/topic" => {
+                let topic = line[command.len()..].trim();
+                Self::Topic { topic: (!topic.is_empty()).then(|| topic.to_owned()) }
+            }
+            "/mode" => {
+                let mode = arg();
+                let change = match mode.as_str() {
+                    "" => None,
+                    "+i" | "-i" => Some(ModeChange::InviteOnly(mode.starts_with('+'))),
+                    "+m" | "-m" => Some(ModeChange::Moderated(mode.starts_with('+'))),
+                    "+s" | "-s" => Some(ModeChange::Secret(mode.starts_with('+'))),
+                    "-k" => Some(ModeChange::Key(None)),
+                    "+k" => match arg() {
+                        key if key.is_empty() => return Err(ParseError::InvalidMode(mode)),
+                        key => Some(ModeChange::Key(Some(key))),
+                    },
+                    "-l" => Some(ModeChange::MaxUsers(None)),
+                    "-d" => Some(ModeChange::SlowMode(None)),
+                    "+d" => {
+                        let slow_secs = arg();
+                        match slow_secs.parse() {
+                            Ok(slow_secs) if slow_secs > 0 => Some(ModeChange::SlowMode(Some(slow_secs))),
+                            _ => return Err(ParseError::InvalidMode(format!("+d {slow_secs}").trim_end().to_owned())),
+                        }
+                    }
+                    "+l" => {
+                        let max_users = arg();
+                        match max_users.parse() {
+                            Ok(max_users) if max_users > 0 => Some(ModeChange::MaxUsers(Some(max_users))),
+                            _ => return Err(ParseError::InvalidMode(format!("+l {max_users}").trim_end().to_owned())),
+                        }
+                    }
+                    _ => return Err(ParseError::InvalidMode(mode)),
+                };
+                Self::Mode { change }
+            }
+            "/protocol" => {
+                let format = match arg().as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    other => return Err(ParseError::UnknownFormat(other.to_owned())),
+                };
+                let version = match arg().as_str() {
+                    "" => PROTOCOL_VERSION,
+                    version => version
+                        .parse()
+                        .map_err(|_| ParseError::InvalidVersion(version.to_owned()))?,
+                };
+                Self::Protocol { format, version }
+            }
+            other => return Err(ParseError::UnknownCommand(other.to_owned())),
+        };
+        Ok(frame)
+    }
+}
+
+// splits off the first word, the rest is kept as typed
+fn split_rest(args: &str) -> (&str, &str) {
+    let args = args.trim_start();
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    (first, rest.trim_start())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub name: String,
+    pub users: usize,
+    pub topic: Option<String>,
+    pub creator: String,
+    // unix time in millis
+    pub created: u64,
+    pub modes: RoomModes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UserInfo {
+    pub name: String,
+    pub role: Role,
+    // unix time in millis
+    pub joined: u64,
+    pub idle_secs: u64,
+    pub away: Option<String>,
+}
+
+impl UserInfo {
+    // "alice", "bob (idle 5m)" or "carol (away: lunch)"
+    pub fn status(&self) -> String {
+        match (&self.away, idle(self.idle_secs)) {
+            (Some(away), _) if away.is_empty() => format!("{} (away)", self.name),
+            (Some(away), _) => format!("{} (away: {away})", self.name),
+            (None, Some(idle)) => format!("{} (idle {idle})", self.name),
+            (None, None) => self.name.clone(),
+        }
+    }
+}
+
+// rough idle time, nobody is idle for under a minute
+pub fn idle(secs: u64) -> Option<String> {
+    (secs >= 60).then(|| duration(secs))
+}
+
+// in the largest whole unit, "45s", "5m", "2h" or "3d"
+pub fn duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WhoisInfo {
+    pub name: String,
+    // hidden when it's a secret room the asker isn't in
+    pub room: Option<String>,
+    // transport and format, like "tcp/text" or "wss/json"
+    pub client: String,
+    pub online_secs: u64,
+    pub idle_secs: u64,
+    pub away: Option<String>,
+}
+
+impl WhoisInfo {
+    pub fn to_text(&self) -> String {
+        let mut text = match &self.room {
+            Some(room) => format!("{} is in {room}", self.name),
+            None => format!("{} is online", self.name),
+        };
+        text.push_str(&format!(
+            " via {}, online {}, idle {}",
+            self.client,
+            duration(self.online_secs),
+            duration(self.idle_secs),
+        ));
+        match self.away.as_deref() {
+            Some("") => text.push_str(", away"),
+            Some(away) => text.push_str(&format!(", away: {away}")),
+            None => (),
+        }
+        text
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub from: String,
+    pub text: String,
+    // unix time in millis
+    pub timestamp: u64,
+    #[serde(default)]
+    pub action: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<Span>,
+}
+
+impl HistoryEntry {
+    // "HH:MM" in UTC, good enough to tell
+    // roughly when something was said
+    pub fn time(&self) -> String {
+        let minutes = self.timestamp / 60_000;
+        format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    // `resume` is only useful to json clients, who
+    // can send it back after reconnecting
+    Welcome {
+        version: u32,
+        name: String,
+        room: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume: Option<String>,
+    },
+    Help { text: String },
+    // `text` is what was typed, `spans` the same text split
+    // up by its markup, `mention` is set when it @mentions you
+    Chat {
+        from: String,
+        text: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        spans: Vec<Span>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        mention: bool,
+    },
+    Action {
+        from: String,
+        text: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        spans: Vec<Span>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        mention: bool,
+    },
+    // you were @mentioned in a room you're not in
+    Mentioned {
+        room: String,
+        from: String,
+        text: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        spans: Vec<Span>,
+    },
+    Direct { from: String, to: String, text: String },
+    Joined { room: String, user: String },
+    Left { room: String, user: String },
+    Renamed { from: String, to: String },
+    RoleChanged { room: String, user: String, role: Role, by: String },
+    Kicked { room: String, user: String, by: String, reason: Option<String> },
+    // `by` is only set when somebody just changed it
+    Topic { room: String, topic: Option<String>, by: Option<String> },
+    Modes { room: String, modes: RoomModes, by: Option<String> },
+    Invited { room: String, by: String },
+    // confirms going away, or answers a message to an away user
+    Away { user: String, message: Option<String> },
+    Whois { user: WhoisInfo },
+    System { text: String },
+    Error { text: String },
+    Rooms { rooms: Vec<RoomInfo> },
+    Users { room: String, users: Vec<UserInfo> },
+    History { room: String, messages: Vec<HistoryEntry> },
+}
+
+impl ServerFrame {
+    pub fn system(text: impl Into<String>) -> Self {
+        Self::System { text: text.into() }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self::Error { text: text.into() }
+    }
+
+    // renders the frame the way plain text clients
+    // see it, `me` is the name of the receiving user
+    pub fn to_text(&self, me: &str) -> String {
+        match self {
+            Self::Welcome { name, .. } => format!("You are {name}"),
+            Self::Help { text } => text.clone(),
+            Self::Chat { from, text, .. } => format!("{from}: {text}"),
+            Self::Action { from, text, .. } => format!("* {from} {text}"),
+            Self::Mentioned { room, from, text, .. } => format!("{from} mentioned you in {room}: {text}"),
+            Self::Direct { to, text, .. } if to != me => format!("[to {to}] {text}"),
+            Self::Direct { from, text, .. } => format!("[from {from}] {text}"),
+            Self::Joined { room, user } if user == me => format!("You joined {room}"),
+            Self::Joined { user, .. } => format!("{user} joined"),
+            Self::Left { room, user } if user == me => format!("You left {room}"),
+            Self::Left { user, .. } => format!("{user} left"),
+            Self::Renamed { to, .. } if to == me => format!("You are now {to}"),
+            Self::Renamed { from, to } => format!("{from} is now {to}"),
+            Self::RoleChanged { user, role, by, .. } if user == me => format!("{by} made you {}", role.as_str()),
+            Self::RoleChanged { user, role, by, .. } => format!("{by} made {user} {}", role.as_str()),
+            Self::Kicked { room, user, by, reason } if user == me => match reason {
+                Some(reason) => format!("You were kicked from {room} by {by}: {reason}"),
+                None => format!("You were kicked from {room} by {by}"),
+            },
+            Self::Kicked { user, by, reason, .. } => match reason {
+                Some(reason) => format!("{user} was kicked by {by} ({reason})"),
+                None => format!("{user} was kicked by {by}"),
+            },
+            Self::System { text } | Self::Error { text } => text.clone(),
+            Self::Topic { topic: Some(topic), by: Some(by), .. } => format!("{by} set the topic to: {topic}"),
+            Self::Topic { room, topic: Some(topic), .. } => format!("Topic of {room}: {topic}"),
+            Self::Topic { room, .. } => format!("No topic in {room}"),
+            Self::Modes { room, modes, by } => {
+                let flags = match modes.flags() {
+                    flags if flags.is_empty() => "none".to_owned(),
+                    flags => flags,
+                };
+                match by {
+                    Some(by) => format!("{by} set modes of {room} to {flags}"),
+                    None => format!("Modes of {room}: {flags}"),
+                }
+            }
+            Self::Away { user, message } => {
+                let who = if user == me { "You are".to_owned() } else { format!("{user} is") };
+                match message {
+                    Some(message) => format!("{who} away: {message}"),
+                    None => format!("{who} away"),
+                }
+            }
+            Self::Whois { user } => user.to_text(),
+            Self::Invited { room, by } => format!("{by} invited you to {room}, /join {room} to accept"),
+            // one room per line, topics may contain anything
+            Self::Rooms { rooms } => {
+                let mut text = String::from("Rooms");
+                for room in rooms {
+                    text.push_str(&format!("\n{} ({})", room.name, room.users));
+                    let flags = room.modes.flags();
+                    if !flags.is_empty() {
+                        text.push_str(&format!(" {flags}"));
+                    }
+                    if let Some(topic) = &room.topic {
+                        text.push_str(&format!(" - {topic}"));
+                    }
+                }
+                text
+            }
+            Self::Users { users, .. } => {
+                let users: Vec<_> = users.iter().map(UserInfo::status).collect();
+                format!("Users - {}", users.join(", "))
+            }
+            Self::History { room, messages } if messages.is_empty() => format!("No history in {room}"),
+            Self::History { room, messages } => {
+                let mut text = format!("History of {room}");
+                for msg in messages {
+                    let sep = if msg.action { " " } else { ": " };
+                    let from = if msg.action { format!("* {}", msg.from) } else { msg.from.clone() };
+                    text.push_str(&format!("\n[{}] {from}{sep}{}", msg.time(), msg.text));
+                }
+                text
+            }
+        }
+    }
+}
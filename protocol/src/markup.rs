@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+// a run of message text with one style, spans
+// don't nest so "*_both_*" is just bold
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "style", rename_all = "snake_case")]
+pub enum Span {
+    Plain { text: String },
+    Bold { text: String },
+    Italic { text: String },
+    Code { text: String },
+    Link { text: String, url: String },
+}
+
+impl Span {
+    pub fn text(&self) -> &str {
+        match self {
+            Span::Plain { text }
+            | Span::Bold { text }
+            | Span::Italic { text }
+            | Span::Code { text }
+            | Span::Link { text, .. } => text,
+        }
+    }
+}
+
+// splits a message into spans, understands *bold*, _italic_,
+// `code`, [text](url) and bare http(s) links, anything that
+// doesn't close properly is left as plain text
+pub fn parse(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+    let mut prev = None;
+    while let Some(c) = rest.chars().next() {
+        let styled = match c {
+            '`' => code(rest),
+            '*' | '_' if !prev.is_some_and(char::is_alphanumeric) => emphasis(rest, c),
+            '[' => link(rest),
+            'h' if !prev.is_some_and(char::is_alphanumeric) => bare_link(rest),
+            _ => None,
+        };
+        match styled {
+            Some((span, len)) => {
+                if !plain.is_empty() {
+                    spans.push(Span::Plain { text: std::mem::take(&mut plain) });
+                }
+                spans.push(span);
+                prev = rest[..len].chars().next_back();
+                rest = &rest[len..];
+            }
+            None => {
+                plain.push(c);
+                prev = Some(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        spans.push(Span::Plain { text: plain });
+    }
+    spans
+}
+
+// each of these gets the text starting at the opening
+// character and returns the span and how much it used
+
+fn code(text: &str) -> Option<(Span, usize)> {
+    let end = text[1..].find('`')? + 1;
+    let code = &text[1..end];
+    (!code.is_empty()).then(|| (Span::Code { text: code.to_owned() }, end + 1))
+}
+
+fn emphasis(text: &str, delim: char) -> Option<(Span, usize)> {
+    let inner = &text[1..];
+    if inner.starts_with(char::is_whitespace) || inner.starts_with(delim) {
+        return None;
+    }
+    // the closing one has to end a word, so
+    // snake_case_names aren't taken for italics
+    let end = inner.match_indices(delim).map(|(i, _)| i).find(|&i| {
+        let before = inner[..i].chars().next_back();
+        let after = inner[i + 1..].chars().next();
+        i > 0 && !before.is_some_and(char::is_whitespace) && !after.is_some_and(char::is_alphanumeric)
+    })?;
+    let text = inner[..end].to_owned();
+    let span = match delim {
+        '*' => Span::Bold { text },
+        _ => Span::Italic { text },
+    };
+    Some((span, end + 2))
+}
+
+fn link(text: &str) -> Option<(Span, usize)> {
+    let close = text.find(']')?;
+    let label = &text[1..close];
+    let target = text[close + 1..].strip_prefix('(')?;
+    let end = target.find(')')?;
+    let url = &target[..end];
+    if label.is_empty() || !is_url(url) || url.contains(char::is_whitespace) {
+        return None;
+    }
+    let span = Span::Link { text: label.to_owned(), url: url.to_owned() };
+    Some((span, close + 1 + 1 + end + 1))
+}
+
+fn bare_link(text: &str) -> Option<(Span, usize)> {
+    let word = &text[..text.find(char::is_whitespace).unwrap_or(text.len())];
+    // sentence punctuation after a link isn't part of it,
+    // closing parens only count if the link opened one
+    let url = word.trim_end_matches(|c: char| {
+        matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"') || (c == ')' && !word.contains('('))
+    });
+    is_url(url).then(|| (Span::Link { text: url.to_owned(), url: url.to_owned() }, url.len()))
+}
+
+fn is_url(text: &str) -> bool {
+    ["https://", "http://"]
+        .iter()
+        .any(|scheme| text.strip_prefix(scheme).is_some_and(|rest| !rest.is_empty()))
+}
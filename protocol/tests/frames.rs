@@ -0,0 +1,325 @@
+use chat_protocol::{
+    markup, ClientFrame, Format, ModeChange, ParseError, Role, RoomInfo, RoomModes, ServerFrame, Span, UserInfo,
+    WhoisInfo, PROTOCOL_VERSION,
+};
+
+#[test]
+fn plain_lines_parse_into_frames() {
+    assert_eq!(
+        ClientFrame::from_line("hello there"),
+        Ok(ClientFrame::Chat { text: "hello there".into() }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/join  rust "),
+        Ok(ClientFrame::Join { room: "rust".into(), key: None }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/name"),
+        Ok(ClientFrame::Rename { name: String::new() }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/protocol json"),
+        Ok(ClientFrame::Protocol { format: Format::Json, version: PROTOCOL_VERSION }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/dance"),
+        Err(ParseError::UnknownCommand("/dance".into())),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/protocol xml"),
+        Err(ParseError::UnknownFormat("xml".into())),
+    );
+}
+
+#[test]
+fn frames_are_tagged_json() {
+    let frame = ClientFrame::Join { room: "rust".into(), key: None };
+    let json = serde_json::to_string(&frame).unwrap();
+    assert_eq!(json, r#"{"type":"join","room":"rust"}"#);
+    assert_eq!(frame.name(), "join");
+    let frame = ClientFrame::SetRole { user: "bob".into(), role: Role::Voice };
+    assert!(serde_json::to_string(&frame).unwrap().starts_with(r#"{"type":"set_role","#));
+    assert_eq!(frame.name(), "set_role");
+    let frame = ClientFrame::Join { room: "rust".into(), key: None };
+    assert_eq!(serde_json::from_str::<ClientFrame>(&json).unwrap(), frame);
+
+    let frame = ServerFrame::Rooms {
+        rooms: vec![RoomInfo {
+            name: "main".into(),
+            users: 2,
+            topic: None,
+            creator: "alice".into(),
+            created: 0,
+            modes: RoomModes::default(),
+        }],
+    };
+    let json = serde_json::to_string(&frame).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"rooms","rooms":[{"name":"main","users":2,"topic":null,"creator":"alice","created":0,"modes":{"invite_only":false,"keyed":false,"moderated":false,"secret":false,"max_users":null,"slow_secs":null}}]}"#,
+    );
+    assert_eq!(serde_json::from_str::<ServerFrame>(&json).unwrap(), frame);
+}
+
+#[test]
+fn frames_render_as_plain_text() {
+    let joined = ServerFrame::Joined { room: "rust".into(), user: "alice".into() };
+    assert_eq!(joined.to_text("alice"), "You joined rust");
+    assert_eq!(joined.to_text("bob"), "alice joined");
+
+    let user = |name: &str, idle_secs, away: Option<&str>| UserInfo {
+        name: name.into(),
+        role: Role::Member,
+        joined: 0,
+        idle_secs,
+        away: away.map(String::from),
+    };
+    let users = ServerFrame::Users {
+        room: "rust".into(),
+        users: vec![
+            user("alice", 59, None),
+            user("bob", 300, None),
+            user("carol", 7200, Some("lunch")),
+            user("dave", 0, Some("")),
+        ],
+    };
+    assert_eq!(users.to_text("bob"), "Users - alice, bob (idle 5m), carol (away: lunch), dave (away)");
+}
+
+#[test]
+fn direct_messages_keep_their_text() {
+    assert_eq!(
+        ClientFrame::from_line("/msg bob  hi  there"),
+        Ok(ClientFrame::Direct { to: "bob".into(), text: "hi  there".into() }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/msg"),
+        Ok(ClientFrame::Direct { to: String::new(), text: String::new() }),
+    );
+}
+
+#[test]
+fn account_commands_take_the_rest_as_password() {
+    assert_eq!(
+        ClientFrame::from_line("/register hunter 2"),
+        Ok(ClientFrame::Register { password: "hunter 2".into() }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/login bob  pass word "),
+        Ok(ClientFrame::Login { name: "bob".into(), password: "pass word".into() }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/login"),
+        Ok(ClientFrame::Login { name: String::new(), password: String::new() }),
+    );
+}
+
+#[test]
+fn moderation_commands_parse() {
+    assert_eq!(
+        ClientFrame::from_line("/op bob"),
+        Ok(ClientFrame::SetRole { user: "bob".into(), role: Role::Operator }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/devoice bob"),
+        Ok(ClientFrame::SetRole { user: "bob".into(), role: Role::Member }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/kick bot  stop  spamming"),
+        Ok(ClientFrame::Kick { user: "bot".into(), reason: Some("stop  spamming".into()) }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/kick bot"),
+        Ok(ClientFrame::Kick { user: "bot".into(), reason: None }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/ban 10.0.0.1"),
+        Ok(ClientFrame::Ban { target: "10.0.0.1".into() }),
+    );
+    let kicked = ServerFrame::Kicked {
+        room: "rust".into(),
+        user: "bot".into(),
+        by: "alice".into(),
+        reason: Some("spam".into()),
+    };
+    assert_eq!(kicked.to_text("bot"), "You were kicked from rust by alice: spam");
+    assert_eq!(kicked.to_text("bob"), "bot was kicked by alice (spam)");
+}
+
+#[test]
+fn topics_and_modes() {
+    assert_eq!(ClientFrame::from_line("/topic"), Ok(ClientFrame::Topic { topic: None }));
+    assert_eq!(
+        ClientFrame::from_line("/topic  all things rust "),
+        Ok(ClientFrame::Topic { topic: Some("all things rust".into()) }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/mode +l 20"),
+        Ok(ClientFrame::Mode { change: Some(ModeChange::MaxUsers(Some(20))) }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/mode -m"),
+        Ok(ClientFrame::Mode { change: Some(ModeChange::Moderated(false)) }),
+    );
+    assert_eq!(ClientFrame::from_line("/mode +l"), Err(ParseError::InvalidMode("+l".into())));
+    assert_eq!(ClientFrame::from_line("/mode +x"), Err(ParseError::InvalidMode("+x".into())));
+
+    let mut modes = RoomModes::default();
+    assert_eq!(modes.flags(), "");
+    modes.apply(ModeChange::Moderated(true));
+    modes.apply(ModeChange::InviteOnly(true));
+    modes.apply(ModeChange::MaxUsers(Some(20)));
+    assert_eq!(modes.flags(), "+iml 20");
+    modes.apply(ModeChange::SlowMode(Some(5)));
+    assert_eq!(modes.flags(), "+imld 20 5");
+    modes.apply(ModeChange::SlowMode(None));
+
+    let rooms = ServerFrame::Rooms {
+        rooms: vec![RoomInfo {
+            name: "rust".into(),
+            users: 2,
+            topic: Some("crabs, mostly".into()),
+            creator: "alice".into(),
+            created: 0,
+            modes,
+        }],
+    };
+    assert_eq!(rooms.to_text("bob"), "Rooms\nrust (2) +iml 20 - crabs, mostly");
+}
+
+#[test]
+fn keys_and_invites() {
+    assert_eq!(
+        ClientFrame::from_line("/join incident hunter2"),
+        Ok(ClientFrame::Join { room: "incident".into(), key: Some("hunter2".into()) }),
+    );
+    assert_eq!(
+        ClientFrame::from_line("/mode +k hunter2"),
+        Ok(ClientFrame::Mode { change: Some(ModeChange::Key(Some("hunter2".into()))) }),
+    );
+    assert_eq!(ClientFrame::from_line("/mode +k"), Err(ParseError::InvalidMode("+k".into())));
+    assert_eq!(ClientFrame::from_line("/invite bob"), Ok(ClientFrame::Invite { user: "bob".into() }));
+
+    let mut modes = RoomModes::default();
+    modes.apply(ModeChange::Key(Some("hunter2".into())));
+    modes.apply(ModeChange::Secret(true));
+    assert_eq!(modes.flags(), "+ks");
+}
+
+#[test]
+fn away_and_whois() {
+    assert_eq!(
+        ClientFrame::from_line("/away  out for lunch "),
+        Ok(ClientFrame::Away { message: Some("out for lunch".into()) }),
+    );
+    assert_eq!(ClientFrame::from_line("/away"), Ok(ClientFrame::Away { message: None }));
+    assert_eq!(ClientFrame::from_line("/whois bob"), Ok(ClientFrame::Whois { user: "bob".into() }));
+
+    let away = ServerFrame::Away { user: "alice".into(), message: Some("lunch".into()) };
+    assert_eq!(away.to_text("alice"), "You are away: lunch");
+    assert_eq!(away.to_text("bob"), "alice is away: lunch");
+    let away = ServerFrame::Away { user: "alice".into(), message: None };
+    assert_eq!(away.to_text("bob"), "alice is away");
+
+    let mut info = WhoisInfo {
+        name: "alice".into(),
+        room: Some("rust".into()),
+        client: "tcp/text".into(),
+        online_secs: 7200,
+        idle_secs: 45,
+        away: None,
+    };
+    assert_eq!(info.to_text(), "alice is in rust via tcp/text, online 2h, idle 45s");
+    info.room = None;
+    info.away = Some("lunch".into());
+    assert_eq!(info.to_text(), "alice is online via tcp/text, online 2h, idle 45s, away: lunch");
+}
+
+#[test]
+fn markup_becomes_spans() {
+    let plain = |text: &str| Span::Plain { text: text.into() };
+    assert_eq!(
+        markup::parse("a *bold* and _quiet_ `x = 1` move"),
+        [
+            plain("a "),
+            Span::Bold { text: "bold".into() },
+            plain(" and "),
+            Span::Italic { text: "quiet".into() },
+            plain(" "),
+            Span::Code { text: "x = 1".into() },
+            plain(" move"),
+        ],
+    );
+    assert_eq!(
+        markup::parse("see [the docs](https://docs.rs/tokio) or https://tokio.rs."),
+        [
+            plain("see "),
+            Span::Link { text: "the docs".into(), url: "https://docs.rs/tokio".into() },
+            plain(" or "),
+            Span::Link { text: "https://tokio.rs".into(), url: "https://tokio.rs".into() },
+            plain("."),
+        ],
+    );
+    // none of these are markup
+    for text in ["snake_case_name", "2*3*4", "* not bold *", "`", "**", "[x](ftp://host)", "http://"] {
+        assert_eq!(markup::parse(text), [plain(text)], "{text}");
+    }
+    assert_eq!(markup::parse(""), []);
+    assert_eq!(markup::parse("`*no* _markup_`"), [Span::Code { text: "*no* _markup_".into() }]);
+}
+
+#[test]
+fn actions() {
+    assert_eq!(ClientFrame::from_line("/me  waves "), Ok(ClientFrame::Action { text: "waves".into() }));
+    assert_eq!(ClientFrame::from_line("/me"), Ok(ClientFrame::Action { text: String::new() }));
+
+    let action = ServerFrame::Action { from: "alice".into(), text: "waves".into(), spans: Vec::new(), mention: false };
+    assert_eq!(action.to_text("bob"), "* alice waves");
+    let json = serde_json::to_string(&ServerFrame::Action {
+        from: "alice".into(),
+        text: "*waves*".into(),
+        spans: markup::parse("*waves*"),
+        mention: false,
+    })
+    .unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"action","from":"alice","text":"*waves*","spans":[{"style":"bold","text":"waves"}]}"#,
+    );
+    // spans are optional for older peers
+    let chat: ServerFrame = serde_json::from_str(r#"{"type":"chat","from":"alice","text":"hi"}"#).unwrap();
+    assert_eq!(chat, ServerFrame::Chat { from: "alice".into(), text: "hi".into(), spans: Vec::new(), mention: false });
+}
+
+#[test]
+fn mentions() {
+    let chat = ServerFrame::Chat { from: "alice".into(), text: "hi @bob".into(), spans: Vec::new(), mention: true };
+    assert_eq!(
+        serde_json::to_string(&chat).unwrap(),
+        r#"{"type":"chat","from":"alice","text":"hi @bob","mention":true}"#,
+    );
+    assert_eq!(chat.to_text("bob"), "alice: hi @bob");
+
+    let mentioned = ServerFrame::Mentioned {
+        room: "rust".into(),
+        from: "alice".into(),
+        text: "@bob can you look?".into(),
+        spans: Vec::new(),
+    };
+    assert_eq!(mentioned.to_text("bob"), "alice mentioned you in rust: @bob can you look?");
+}
+
+#[test]
+fn resume_tokens() {
+    assert_eq!(ClientFrame::from_line("/resume 0a1b2c"), Ok(ClientFrame::Resume { token: "0a1b2c".into() }));
+    let welcome = ServerFrame::Welcome {
+        version: PROTOCOL_VERSION,
+        name: "alice".into(),
+        room: "main".into(),
+        resume: None,
+    };
+    let json = serde_json::to_string(&welcome).unwrap();
+    assert!(!json.contains("resume"));
+    let welcome: ServerFrame = serde_json::from_str(r#"{"type":"welcome","version":1,"name":"alice","room":"main","resume":"0a1b2c"}"#).unwrap();
+    assert!(matches!(welcome, ServerFrame::Welcome { resume: Some(token), .. } if token == "0a1b2c"));
+}
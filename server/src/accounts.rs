@@ -0,0 +1,150 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use compact_str::CompactString;
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
+
+pub const MIN_PASSWORD_LEN: usize = 6;
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    name: CompactString,
+    // argon2 hash in PHC string format
+    hash: String,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    AlreadyRegistered,
+    PasswordTooShort,
+    Io(io::Error),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyRegistered => f.write_str("Name is already registered"),
+            Self::PasswordTooShort => {
+                write!(f, "Password must be at least {MIN_PASSWORD_LEN} chars")
+            }
+            Self::Io(_) => f.write_str("Couldn't save account, try again later"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+// registered names and their password hashes, loaded from
+// and appended to a json lines file when a path is given
+#[derive(Clone)]
+pub struct Accounts {
+    accounts: Arc<DashMap<CompactString, Arc<str>>>,
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl Accounts {
+    pub fn new() -> Self {
+        Self {
+            accounts: Arc::new(DashMap::new()),
+            file: None,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let accounts = DashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for (n, line) in BufReader::new(file).lines().enumerate() {
+                    match serde_json::from_str::<Account>(&line?) {
+                        Ok(account) => {
+                            accounts.insert(account.name, Arc::from(account.hash));
+                        }
+                        // one torn or corrupt line shouldn't
+                        // keep the server from starting
+                        Err(err) => tracing::warn!("skipping bad account line {}: {err}", n + 1),
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            accounts: Arc::new(accounts),
+            file: Some(Arc::new(Mutex::new(file))),
+        })
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    pub async fn register(&self, name: &str, password: String) -> Result<(), AccountError> {
+        if password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(AccountError::PasswordTooShort);
+        }
+        if self.is_registered(name) {
+            return Err(AccountError::AlreadyRegistered);
+        }
+        // hashing is deliberately slow, keep it
+        // off the runtime's worker threads
+        let hash = tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .map_err(|err| AccountError::Io(io::Error::other(err)))?
+        .map_err(|err| AccountError::Io(io::Error::other(err)))?;
+        // claim the name first so a racing register loses,
+        // then let go of the map before touching the disk
+        match self.accounts.entry(name.into()) {
+            Entry::Vacant(entry) => entry.insert(Arc::from(hash.as_str())),
+            Entry::Occupied(_) => return Err(AccountError::AlreadyRegistered),
+        };
+        let Some(file) = self.file.clone() else {
+            return Ok(());
+        };
+        let account = Account { name: name.into(), hash };
+        let written = tokio::task::spawn_blocking(move || {
+            let mut line = serde_json::to_string(&account)?;
+            line.push('\n');
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            file.write_all(line.as_bytes())
+        })
+        .await
+        .unwrap_or_else(|err| Err(io::Error::other(err)));
+        if let Err(err) = written {
+            self.accounts.remove(name);
+            return Err(AccountError::Io(err));
+        }
+        Ok(())
+    }
+
+    pub async fn verify(&self, name: &str, password: String) -> bool {
+        let Some(hash) = self.accounts.get(name).map(|hash| hash.clone()) else {
+            return false;
+        };
+        tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hash)
+                .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+                .unwrap_or(false)
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+impl Default for Accounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use clap::{CommandFactory, Parser};
+use serde::Deserialize;
+use crate::{
+    valid_name, HistoryBackend, LogFormat, LogRotation, CHAT_BURST, CHAT_REFILL_MS, COMMAND_BURST,
+    COMMAND_REFILL_MS, DEFAULT_LOG_FILTER, FLOOD_MUTE_SECS, HISTORY_PATH, HISTORY_REPLAY, HISTORY_SIZE,
+    MAIN, MAX_MSG_LEN, RESUME_GRACE_SECS, ROOM_CHANNEL_CAPACITY, SHUTDOWN_GRACE_SECS, SHUTDOWN_TIMEOUT_SECS,
+};
+
+pub const DEFAULT_BIND: &str = "0.0.0.0:8080";
+pub const MAX_CONNECTIONS: usize = 10_000;
+
+// every setting can come from a flag, an env var or
+// the config file, in that order of precedence, so
+// all of them are optional here and defaults are
+// only filled in once everything has been merged
+#[derive(Parser, Debug, Default)]
+#[command(name = "chat-server", version, about = "Tokio chat server")]
+pub struct Cli {
+    /// TOML config file
+    #[arg(short, long, env = "CHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Addresses to listen on, comma separated
+    #[arg(short, long, env = "CHAT_BIND", value_delimiter = ',')]
+    pub bind: Vec<SocketAddr>,
+    /// Addresses to accept websocket clients on, comma separated
+    #[arg(long, env = "CHAT_WS_BIND", value_delimiter = ',')]
+    pub ws_bind: Vec<SocketAddr>,
+    /// Addresses to serve /metrics and /healthz on, comma separated
+    #[arg(long, env = "CHAT_METRICS_BIND", value_delimiter = ',')]
+    pub metrics_bind: Vec<SocketAddr>,
+    /// Max length of a single message
+    #[arg(long, env = "CHAT_MAX_MSG_LEN")]
+    pub max_msg_len: Option<usize>,
+    /// Capacity of each room's broadcast channel
+    #[arg(long, env = "CHAT_ROOM_CHANNEL_CAPACITY")]
+    pub room_channel_capacity: Option<usize>,
+    /// Room users are put in when they connect
+    #[arg(long, env = "CHAT_MAIN_ROOM")]
+    pub main_room: Option<String>,
+    /// Max number of simultaneous connections
+    #[arg(long, env = "CHAT_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+    /// Directory to write log files to
+    #[arg(long, env = "CHAT_LOG_DIR")]
+    pub log_dir: Option<PathBuf>,
+    /// How often log files are rotated
+    #[arg(long, env = "CHAT_LOG_ROTATION")]
+    pub log_rotation: Option<LogRotation>,
+    /// Log line format
+    #[arg(long, env = "CHAT_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// Log filter directives, e.g. "info,chat_server=debug"
+    #[arg(long, env = "RUST_LOG")]
+    pub log_filter: Option<String>,
+    /// Where room history is kept
+    #[arg(long, env = "CHAT_HISTORY")]
+    pub history: Option<HistoryBackend>,
+    /// History log file, used by the file backend
+    #[arg(long, env = "CHAT_HISTORY_PATH")]
+    pub history_path: Option<PathBuf>,
+    /// Messages kept per room
+    #[arg(long, env = "CHAT_HISTORY_SIZE")]
+    pub history_size: Option<usize>,
+    /// Messages replayed when joining a room
+    #[arg(long, env = "CHAT_HISTORY_REPLAY")]
+    pub history_replay: Option<usize>,
+    /// PEM certificate chain, enables TLS together with --tls-key
+    #[arg(long, env = "CHAT_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "CHAT_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Account store file, accounts are kept in memory if unset
+    #[arg(long, env = "CHAT_ACCOUNTS_PATH")]
+    pub accounts_path: Option<PathBuf>,
+    /// Only allow /help, /login and /register until logged in
+    #[arg(
+        long,
+        env = "CHAT_REQUIRE_LOGIN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+    )]
+    pub require_login: Option<bool>,
+    /// Chat messages a user can send in a quick burst
+    #[arg(long, env = "CHAT_CHAT_BURST")]
+    pub chat_burst: Option<u32>,
+    /// Millis it takes to earn back one chat message
+    #[arg(long, env = "CHAT_CHAT_REFILL_MS")]
+    pub chat_refill_ms: Option<u64>,
+    /// Commands a user can send in a quick burst
+    #[arg(long, env = "CHAT_COMMAND_BURST")]
+    pub command_burst: Option<u32>,
+    /// Millis it takes to earn back one command
+    #[arg(long, env = "CHAT_COMMAND_REFILL_MS")]
+    pub command_refill_ms: Option<u64>,
+    /// How long flooding users are muted for
+    #[arg(long, env = "CHAT_FLOOD_MUTE_SECS")]
+    pub flood_mute_secs: Option<u64>,
+    /// How long users are warned before shutting down
+    #[arg(long, env = "CHAT_SHUTDOWN_GRACE_SECS")]
+    pub shutdown_grace_secs: Option<u64>,
+    /// How long sessions get to close before exiting anyway
+    #[arg(long, env = "CHAT_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// How long dropped json sessions can be resumed for, 0 to disable
+    #[arg(long, env = "CHAT_RESUME_GRACE_SECS")]
+    pub resume_grace_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: Vec<SocketAddr>,
+    pub ws_bind: Vec<SocketAddr>,
+    pub metrics_bind: Vec<SocketAddr>,
+    pub max_msg_len: usize,
+    pub room_channel_capacity: usize,
+    pub main_room: String,
+    pub max_connections: usize,
+    pub log_dir: Option<PathBuf>,
+    pub log_rotation: LogRotation,
+    pub log_format: LogFormat,
+    pub log_filter: String,
+    pub history: HistoryBackend,
+    pub history_path: PathBuf,
+    pub history_size: usize,
+    pub history_replay: usize,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub accounts_path: Option<PathBuf>,
+    pub require_login: bool,
+    pub chat_burst: u32,
+    pub chat_refill_ms: u64,
+    pub command_burst: u32,
+    pub command_refill_ms: u64,
+    pub flood_mute_secs: u64,
+    pub shutdown_grace_secs: u64,
+    pub shutdown_timeout_secs: u64,
+    pub resume_grace_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![DEFAULT_BIND.parse().unwrap()],
+            ws_bind: Vec::new(),
+            metrics_bind: Vec::new(),
+            max_msg_len: MAX_MSG_LEN,
+            room_channel_capacity: ROOM_CHANNEL_CAPACITY,
+            main_room: MAIN.to_owned(),
+            max_connections: MAX_CONNECTIONS,
+            log_dir: None,
+            log_rotation: LogRotation::default(),
+            log_format: LogFormat::default(),
+            log_filter: DEFAULT_LOG_FILTER.to_owned(),
+            history: HistoryBackend::default(),
+            history_path: PathBuf::from(HISTORY_PATH),
+            history_size: HISTORY_SIZE,
+            history_replay: HISTORY_REPLAY,
+            tls_cert: None,
+            tls_key: None,
+            accounts_path: None,
+            require_login: false,
+            chat_burst: CHAT_BURST,
+            chat_refill_ms: CHAT_REFILL_MS,
+            command_burst: COMMAND_BURST,
+            command_refill_ms: COMMAND_REFILL_MS,
+            flood_mute_secs: FLOOD_MUTE_SECS,
+            shutdown_grace_secs: SHUTDOWN_GRACE_SECS,
+            shutdown_timeout_secs: SHUTDOWN_TIMEOUT_SECS,
+            resume_grace_secs: RESUME_GRACE_SECS,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "can't read {}: {err}", path.display()),
+            Self::Parse(path, err) => write!(f, "can't parse {}: {err}", path.display()),
+            Self::Invalid(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    // parses the process args and exits with
+    // a clap style error if anything is wrong
+    pub fn parse() -> Self {
+        match Self::from_cli(Cli::parse()) {
+            Ok(config) => config,
+            Err(err) => Cli::command()
+                .error(clap::error::ErrorKind::InvalidValue, err)
+                .exit(),
+        }
+    }
+
+    pub fn from_cli(cli: Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        if !cli.bind.is_empty() {
+            config.bind = cli.bind;
+        }
+        if !cli.ws_bind.is_empty() {
+            config.ws_bind = cli.ws_bind;
+        }
+        if !cli.metrics_bind.is_empty() {
+            config.metrics_bind = cli.metrics_bind;
+        }
+        if let Some(max_msg_len) = cli.max_msg_len {
+            config.max_msg_len = max_msg_len;
+        }
+        if let Some(capacity) = cli.room_channel_capacity {
+            config.room_channel_capacity = capacity;
+        }
+        if let Some(main_room) = cli.main_room {
+            config.main_room = main_room;
+        }
+        if let Some(max_connections) = cli.max_connections {
+            config.max_connections = max_connections;
+        }
+        if cli.log_dir.is_some() {
+            config.log_dir = cli.log_dir;
+        }
+        if let Some(rotation) = cli.log_rotation {
+            config.log_rotation = rotation;
+        }
+        if let Some(format) = cli.log_format {
+            config.log_format = format;
+        }
+        if let Some(filter) = cli.log_filter {
+            config.log_filter = filter;
+        }
+        if let Some(history) = cli.history {
+            config.history = history;
+        }
+        if let Some(path) = cli.history_path {
+            config.history_path = path;
+        }
+        if let Some(size) = cli.history_size {
+            config.history_size = size;
+        }
+        if let Some(replay) = cli.history_replay {
+            config.history_replay = replay;
+        }
+        if cli.tls_cert.is_some() {
+            config.tls_cert = cli.tls_cert;
+        }
+        if cli.tls_key.is_some() {
+            config.tls_key = cli.tls_key;
+        }
+        if cli.accounts_path.is_some() {
+            config.accounts_path = cli.accounts_path;
+        }
+        if let Some(require_login) = cli.require_login {
+            config.require_login = require_login;
+        }
+        if let Some(burst) = cli.chat_burst {
+            config.chat_burst = burst;
+        }
+        if let Some(refill_ms) = cli.chat_refill_ms {
+            config.chat_refill_ms = refill_ms;
+        }
+        if let Some(burst) = cli.command_burst {
+            config.command_burst = burst;
+        }
+        if let Some(refill_ms) = cli.command_refill_ms {
+            config.command_refill_ms = refill_ms;
+        }
+        if let Some(mute_secs) = cli.flood_mute_secs {
+            config.flood_mute_secs = mute_secs;
+        }
+        if let Some(grace_secs) = cli.shutdown_grace_secs {
+            config.shutdown_grace_secs = grace_secs;
+        }
+        if let Some(timeout_secs) = cli.shutdown_timeout_secs {
+            config.shutdown_timeout_secs = timeout_secs;
+        }
+        if let Some(grace_secs) = cli.resume_grace_secs {
+            config.resume_grace_secs = grace_secs;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Read(path.to_owned(), err))?;
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_owned(), err))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind.is_empty() {
+            return Err(ConfigError::Invalid("at least one bind address is required"));
+        }
+        if self.max_msg_len == 0 {
+            return Err(ConfigError::Invalid("max_msg_len must be greater than 0"));
+        }
+        // tokio's broadcast channel panics on a capacity of 0
+        if self.room_channel_capacity == 0 {
+            return Err(ConfigError::Invalid("room_channel_capacity must be greater than 0"));
+        }
+        if self.max_connections == 0 {
+            return Err(ConfigError::Invalid("max_connections must be greater than 0"));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(ConfigError::Invalid("tls_cert and tls_key must be set together"));
+        }
+        if !valid_name(Some(&self.main_room)) {
+            return Err(ConfigError::Invalid("main_room must be 2 - 20 alphanumeric chars"));
+        }
+        if self.chat_burst == 0 || self.command_burst == 0 {
+            return Err(ConfigError::Invalid("chat_burst and command_burst must be greater than 0"));
+        }
+        if self.chat_refill_ms == 0 || self.command_refill_ms == 0 {
+            return Err(ConfigError::Invalid("chat_refill_ms and command_refill_ms must be greater than 0"));
+        }
+        Ok(())
+    }
+}
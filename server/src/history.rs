@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use clap::ValueEnum;
+use compact_str::CompactString;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use crate::{unix_millis, ServerConfig};
+
+pub const HISTORY_SIZE: usize = 500;
+pub const HISTORY_REPLAY: usize = 20;
+pub const HISTORY_PATH: &str = "history.log";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StoredMsg {
+    pub room: CompactString,
+    pub from: CompactString,
+    pub text: Arc<str>,
+    // unix time in millis
+    pub timestamp: u64,
+    // sent with /me, older logs don't have it
+    #[serde(default)]
+    pub action: bool,
+}
+
+impl StoredMsg {
+    pub fn now(room: &str, from: &str, text: Arc<str>) -> Self {
+        Self {
+            room: room.into(),
+            from: from.into(),
+            text,
+            timestamp: unix_millis(),
+            action: false,
+        }
+    }
+}
+
+pub trait MessageStore: Send + Sync {
+    fn append(&self, msg: StoredMsg);
+    // oldest first
+    fn recent(&self, room: &str, count: usize) -> Vec<StoredMsg>;
+    // forgets everything said in the room
+    fn clear(&self, room: &str);
+    // called once on shutdown
+    fn flush(&self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryBackend {
+    #[default]
+    Memory,
+    File,
+}
+
+pub fn open_store(config: &ServerConfig) -> Result<Arc<dyn MessageStore>, io::Error> {
+    Ok(match config.history {
+        HistoryBackend::Memory => Arc::new(MemoryStore::new(config.history_size)),
+        HistoryBackend::File => Arc::new(FileStore::open(&config.history_path, config.history_size)?),
+    })
+}
+
+// keeps the last `capacity` messages of every room
+pub struct MemoryStore {
+    rooms: DashMap<CompactString, VecDeque<StoredMsg>>,
+    capacity: usize,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            rooms: DashMap::new(),
+            capacity,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.rooms.iter().map(|room| room.len()).sum()
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn append(&self, msg: StoredMsg) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut room = self
+            .rooms
+            .entry(msg.room.clone())
+            .or_insert_with(|| VecDeque::with_capacity(self.capacity.min(64)));
+        if room.len() == self.capacity {
+            room.pop_front();
+        }
+        room.push_back(msg);
+    }
+
+    fn recent(&self, room: &str, count: usize) -> Vec<StoredMsg> {
+        let Some(room) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+        let skip = room.len().saturating_sub(count);
+        room.iter().skip(skip).cloned().collect()
+    }
+
+    fn clear(&self, room: &str) {
+        self.rooms.remove(room);
+    }
+}
+
+// append-only json lines log, with a memory store in
+// front of it so reads never touch the disk. the log
+// is loaded on startup so history survives restarts,
+// and written by its own thread so sessions never wait
+// on the disk
+pub struct FileStore {
+    cache: MemoryStore,
+    // taken on drop so the writer sees the channel close
+    ops: Option<Sender<LogOp>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+enum LogOp {
+    Append(StoredMsg),
+    // drop the room's messages from the log
+    Clear(CompactString),
+    Flush(Sender<Result<(), io::Error>>),
+}
+
+impl FileStore {
+    pub fn open(path: &Path, capacity: usize) -> Result<Self, io::Error> {
+        let cache = MemoryStore::new(capacity);
+        let mut logged = 0;
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    logged += 1;
+                    match serde_json::from_str(&line) {
+                        Ok(msg) => cache.append(msg),
+                        // a crash mid-write can leave a torn
+                        // last line, skip it rather than fail
+                        Err(err) => tracing::warn!("skipping bad history line {logged}: {err}"),
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        // the log only ever grows, so once it holds a lot
+        // more than we keep around rewrite it from the cache
+        if logged > cache.len() * 2 {
+            compact(path, &cache)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (ops, rx) = mpsc::channel();
+        let path = path.to_owned();
+        let writer = std::thread::Builder::new()
+            .name("history-log".into())
+            .spawn(move || write_log(BufWriter::new(file), &path, rx))?;
+        Ok(Self {
+            cache,
+            ops: Some(ops),
+            writer: Some(writer),
+        })
+    }
+
+    fn send(&self, op: LogOp) {
+        if let Some(ops) = &self.ops
+            && ops.send(op).is_err()
+        {
+            tracing::error!("history log writer is gone");
+        }
+    }
+}
+
+// waits for queued writes so nothing is lost on the way out
+impl Drop for FileStore {
+    fn drop(&mut self) {
+        self.ops.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+// runs until the store is dropped, flushing whenever
+// it has caught up with the sessions
+fn write_log(mut log: BufWriter<File>, path: &Path, ops: Receiver<LogOp>) {
+    loop {
+        let op = match ops.try_recv() {
+            Ok(op) => op,
+            Err(TryRecvError::Empty) => {
+                if let Err(err) = log.flush() {
+                    tracing::error!("failed to write history: {err}");
+                }
+                match ops.recv() {
+                    Ok(op) => op,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        match op {
+            LogOp::Append(msg) => {
+                let written = serde_json::to_writer(&mut log, &msg)
+                    .map_err(io::Error::from)
+                    .and_then(|_| log.write_all(b"\n"));
+                if let Err(err) = written {
+                    tracing::error!("failed to write history: {err}");
+                }
+            }
+            // everything queued before is in the log already,
+            // and what comes after belongs to a new room
+            LogOp::Clear(room) => {
+                let cleared = log
+                    .flush()
+                    .and_then(|_| clear_log(path, &room))
+                    .and_then(|_| OpenOptions::new().append(true).open(path));
+                match cleared {
+                    Ok(file) => log = BufWriter::new(file),
+                    Err(err) => tracing::error!("failed to clear history of {room}: {err}"),
+                }
+            }
+            LogOp::Flush(done) => {
+                let _ = done.send(log.flush().and_then(|_| log.get_ref().sync_data()));
+            }
+        }
+    }
+    if let Err(err) = log.flush() {
+        tracing::error!("failed to write history: {err}");
+    }
+}
+
+fn compact(path: &Path, cache: &MemoryStore) -> Result<(), io::Error> {
+    let mut msgs: Vec<_> = cache.rooms.iter().flat_map(|room| room.clone()).collect();
+    msgs.sort_by_key(|msg| msg.timestamp);
+    rewrite(path, msgs)
+}
+
+fn clear_log(path: &Path, room: &str) -> Result<(), io::Error> {
+    let mut msgs = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(msg) = serde_json::from_str::<StoredMsg>(&line?)
+            && msg.room != room
+        {
+            msgs.push(msg);
+        }
+    }
+    rewrite(path, msgs)
+}
+
+fn rewrite(path: &Path, msgs: Vec<StoredMsg>) -> Result<(), io::Error> {
+    let mut tmp_path = PathBuf::from(path);
+    tmp_path.set_extension("compacting");
+    let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+    for msg in msgs {
+        serde_json::to_writer(&mut tmp, &msg)?;
+        tmp.write_all(b"\n")?;
+    }
+    tmp.flush()?;
+    fs::rename(tmp_path, path)
+}
+
+impl MessageStore for FileStore {
+    fn append(&self, msg: StoredMsg) {
+        self.cache.append(msg.clone());
+        self.send(LogOp::Append(msg));
+    }
+
+    fn recent(&self, room: &str, count: usize) -> Vec<StoredMsg> {
+        self.cache.recent(room, count)
+    }
+
+    // the log is rewritten without the room in the background
+    fn clear(&self, room: &str) {
+        self.cache.clear(room);
+        self.send(LogOp::Clear(room.into()));
+    }
+
+    // waits for the writer to catch up and sync to disk
+    fn flush(&self) -> Result<(), io::Error> {
+        let (done, result) = mpsc::channel();
+        self.send(LogOp::Flush(done));
+        result.recv().map_err(|_| io::Error::other("history log writer is gone"))?
+    }
+}
@@ -0,0 +1,52 @@
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub(crate) const MAX_REQUEST_HEAD: usize = 8 * 1024;
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const OK: &str = "200 OK";
+pub(crate) const NOT_FOUND: &str = "404 Not Found";
+pub(crate) const METHOD_NOT_ALLOWED: &str = "405 Method Not Allowed";
+
+// just enough http for the bundled page, websocket
+// upgrades and the metrics endpoint, every response
+// closes the connection so there's no keep alive
+
+pub(crate) async fn read_request_head<S: AsyncRead + Unpin>(conn: &mut S) -> Result<Vec<u8>, io::Error> {
+    let mut head = Vec::with_capacity(1024);
+    let mut buf = [0; 1024];
+    loop {
+        let n = conn.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.windows(4).any(|window| window == b"\r\n\r\n") {
+            return Ok(head);
+        }
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+    }
+}
+
+// method and path of the request
+pub(crate) fn request_line(head: &[u8]) -> (Option<&str>, Option<&str>) {
+    let head = std::str::from_utf8(head).unwrap_or_default();
+    let mut request_line = head.lines().next().unwrap_or_default().split_ascii_whitespace();
+    (request_line.next(), request_line.next())
+}
+
+pub(crate) async fn write_response<S: AsyncWrite + Unpin>(
+    conn: &mut S,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<(), io::Error> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+    conn.write_all(response.as_bytes()).await?;
+    conn.shutdown().await
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use compact_str::CompactString;
+use dashmap::DashMap;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+pub const INBOX_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct DirectMsg {
+    pub from: CompactString,
+    pub to: CompactString,
+    pub text: Arc<str>,
+}
+
+#[derive(Clone, Debug)]
+pub enum InboxMsg {
+    Direct(DirectMsg),
+    Invite {
+        room: CompactString,
+        by: CompactString,
+    },
+    // an @mention from a room the user isn't in
+    Mention {
+        room: CompactString,
+        from: CompactString,
+        text: Arc<str>,
+    },
+}
+
+pub enum DeliveryError {
+    Offline,
+    Full,
+}
+
+// every connected user has an inbox other sessions
+// can drop direct messages into, regardless of which
+// room either of them is in
+#[derive(Clone)]
+#[repr(transparent)]
+pub struct Inboxes(Arc<DashMap<CompactString, mpsc::Sender<InboxMsg>>>);
+
+impl Inboxes {
+    pub fn new() -> Self {
+        Self(Arc::new(DashMap::with_capacity(32)))
+    }
+
+    pub fn register(&self, name: &str) -> mpsc::Receiver<InboxMsg> {
+        let (tx, rx) = mpsc::channel(INBOX_CAPACITY);
+        self.0.insert(name.into(), tx);
+        rx
+    }
+
+    pub fn remove(&self, name: &str) {
+        self.0.remove(name);
+    }
+
+    pub fn rename(&self, prev: &str, next: &str) {
+        if let Some((_, tx)) = self.0.remove(prev) {
+            self.0.insert(next.into(), tx);
+        }
+    }
+
+    // never waits on a slow recipient, if their inbox
+    // is full the sender is told to try again later
+    pub fn send(&self, msg: DirectMsg) -> Result<(), DeliveryError> {
+        let to = msg.to.clone();
+        self.deliver(&to, InboxMsg::Direct(msg))
+    }
+
+    pub fn invite(&self, to: &str, room: &str, by: &str) -> Result<(), DeliveryError> {
+        self.deliver(to, InboxMsg::Invite { room: room.into(), by: by.into() })
+    }
+
+    pub fn mention(&self, to: &str, room: &str, from: &str, text: Arc<str>) -> Result<(), DeliveryError> {
+        self.deliver(to, InboxMsg::Mention { room: room.into(), from: from.into(), text })
+    }
+
+    fn deliver(&self, to: &str, msg: InboxMsg) -> Result<(), DeliveryError> {
+        let Some(tx) = self.0.get(to).map(|tx| tx.clone()) else {
+            return Err(DeliveryError::Offline);
+        };
+        match tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(DeliveryError::Full),
+            Err(TrySendError::Closed(_)) => Err(DeliveryError::Offline),
+        }
+    }
+}
+
+impl Default for Inboxes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,13 +1,55 @@
 #![allow(dead_code, unused_imports, unused_variables)]
 
+use std::time::{SystemTime, UNIX_EPOCH};
 use compact_str::CompactString;
 
+// generated word lists, left as they were written
+#[allow(clippy::redundant_static_lifetimes)]
 mod characters;
+#[allow(clippy::redundant_static_lifetimes)]
 mod adjectives;
+mod accounts;
+mod config;
+mod history;
+mod http;
+mod inboxes;
+mod logging;
+mod metrics;
+mod names;
+mod ratelimit;
+mod resume;
+mod rooms;
+mod server;
+mod session;
+mod tls;
+mod websocket;
 
 use characters::CHARACTERS;
 use adjectives::ADJECTIVES;
 
+pub use accounts::{AccountError, Accounts, MIN_PASSWORD_LEN};
+pub use config::{Cli, ConfigError, ServerConfig, DEFAULT_BIND, MAX_CONNECTIONS};
+pub use history::{
+    open_store, FileStore, HistoryBackend, MemoryStore, MessageStore, StoredMsg, HISTORY_PATH,
+    HISTORY_REPLAY, HISTORY_SIZE,
+};
+pub use inboxes::{DeliveryError, DirectMsg, InboxMsg, Inboxes, INBOX_CAPACITY};
+pub use logging::{init_logging, LogFormat, LogRotation, DEFAULT_LOG_FILTER, LOG_FILE_PREFIX};
+pub use metrics::{handle_metrics, render_metrics, Metrics};
+pub use names::Names;
+pub use ratelimit::{
+    LimiterStats, RateLimiter, TokenBucket, Traffic, Verdict, CHAT_BURST, CHAT_REFILL_MS, COMMAND_BURST,
+    COMMAND_REFILL_MS, FLOOD_MUTE_SECS,
+};
+pub use resume::{Resumes, RESUME_GRACE_SECS};
+pub use rooms::{JoinError, ModError, Room, RoomMsg, Rooms, ROOM_CHANNEL_CAPACITY};
+pub use server::{
+    serve, serve_with_shutdown, Shared, SHUTDOWN_GRACE_SECS, SHUTDOWN_TIMEOUT_SECS, TLS_HANDSHAKE_TIMEOUT,
+};
+pub use session::{handle_lines, handle_user, Peer, SessionError, User, HELP_MSG, MAIN, MAX_MSG_LEN};
+pub use tls::{load_certs, load_key, tls_acceptor};
+pub use websocket::{handle_websocket, INDEX_HTML};
+
 pub fn choose<T: Copy>(arrays: &[T]) -> T{
     arrays[fastrand::usize(..arrays.len())]
 }
@@ -35,9 +77,12 @@ impl NameGenerator {
             adj_offset: fastrand::usize(..ADJECTIVES.len()),
             char_idx: 0,
             char_offset_idx: 0,
-            char_offsets: char_offsets,
+            char_offsets,
         }
     }
+    // never runs out of names, it just starts
+    // cycling through combinations again
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> CompactString {
         let (adj, character) = loop {
             let adj =
@@ -67,16 +112,21 @@ impl NameGenerator {
     }
 }
 
-#[macro_export]
-macro_rules! b {
-    ($result:expr) => {
-        match $result {
-            Ok(v) => v,
-            Err(e) => panic!("Error: {}", e),
-        }
-    };
+impl Default for NameGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+// unix time in millis
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+
 pub fn valid_name(name: Option<&str>) -> bool {
     match name {
         None => false,
@@ -92,4 +142,4 @@ pub fn valid_name(name: Option<&str>) -> bool {
                 .all(|c| char::is_ascii_alphanumeric(&c) || c == '-' || c == '_')
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,65 @@
+use std::io;
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing_appender::{non_blocking::WorkerGuard, rolling::{RollingFileAppender, Rotation}};
+use tracing_subscriber::{fmt, EnvFilter};
+use crate::ServerConfig;
+
+pub const DEFAULT_LOG_FILTER: &str = "info";
+pub const LOG_FILE_PREFIX: &str = "chat-server.log";
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    #[default]
+    Daily,
+    Hourly,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+// installs the global subscriber, logs go to stdout unless
+// a log dir is configured. the returned guard flushes any
+// buffered logs when dropped so main must hold on to it
+pub fn init_logging(config: &ServerConfig) -> Result<WorkerGuard, io::Error> {
+    let filter = EnvFilter::try_new(&config.log_filter)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let (writer, guard) = match &config.log_dir {
+        Some(dir) => {
+            let appender = RollingFileAppender::builder()
+                .rotation(config.log_rotation.into())
+                .filename_prefix(LOG_FILE_PREFIX)
+                .build(dir)
+                .map_err(io::Error::other)?;
+            tracing_appender::non_blocking(appender)
+        }
+        None => tracing_appender::non_blocking(io::stdout()),
+    };
+    let builder = fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(config.log_dir.is_none());
+    let result = match config.log_format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    };
+    result.map_err(io::Error::other)?;
+    Ok(guard)
+}
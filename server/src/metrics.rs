@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use dashmap::DashMap;
+use tokio::io::{AsyncRead, AsyncWrite};
+use crate::{
+    http::{read_request_head, request_line, write_response, METHOD_NOT_ALLOWED, NOT_FOUND, OK, REQUEST_TIMEOUT},
+    Shared,
+};
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// counters sessions bump as they go, gauges like the
+// number of users or rooms are read off the shared
+// state whenever metrics are scraped
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub connections: AtomicU64,
+    pub connections_total: AtomicU64,
+    pub messages: AtomicU64,
+    pub direct_messages: AtomicU64,
+    pub lagged: AtomicU64,
+    commands: DashMap<&'static str, u64>,
+    errors: DashMap<io::ErrorKind, u64>,
+}
+
+impl Metrics {
+    pub fn command(&self, name: &'static str) {
+        *self.commands.entry(name).or_default() += 1;
+    }
+
+    pub fn error(&self, kind: io::ErrorKind) {
+        *self.errors.entry(kind).or_default() += 1;
+    }
+
+    pub fn commands(&self, name: &str) -> u64 {
+        self.commands.get(name).map(|count| *count).unwrap_or_default()
+    }
+
+    pub fn errors(&self, kind: io::ErrorKind) -> u64 {
+        self.errors.get(&kind).map(|count| *count).unwrap_or_default()
+    }
+}
+
+// prometheus text exposition format
+pub fn render_metrics(shared: &Shared) -> String {
+    let metrics = &shared.metrics;
+    let limiter = &shared.limiter_stats;
+    let mut out = String::with_capacity(4096);
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{name}{labels} {value}");
+        }
+    };
+    let unlabeled = |value: u64| vec![(String::new(), value)];
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    metric("chat_users", "gauge", "Users currently holding a name", unlabeled(shared.names.len() as u64));
+    metric("chat_connections", "gauge", "Open connections", unlabeled(load(&metrics.connections)));
+    metric("chat_connections_total", "counter", "Connections accepted", unlabeled(load(&metrics.connections_total)));
+    let rooms = shared.rooms.receiver_counts();
+    metric("chat_rooms", "gauge", "Open rooms", unlabeled(rooms.len() as u64));
+    metric(
+        "chat_room_receivers",
+        "gauge",
+        "Receivers subscribed to each room's channel",
+        rooms
+            .into_iter()
+            .map(|(room, receivers)| (label("room", &room), receivers as u64))
+            .collect(),
+    );
+    metric(
+        "chat_messages_total",
+        "counter",
+        "Chat messages sent to rooms, use rate() for messages per second",
+        unlabeled(load(&metrics.messages)),
+    );
+    metric("chat_direct_messages_total", "counter", "Direct messages delivered", unlabeled(load(&metrics.direct_messages)));
+    metric(
+        "chat_lagged_messages_total",
+        "counter",
+        "Room messages dropped for receivers that fell behind",
+        unlabeled(load(&metrics.lagged)),
+    );
+    metric(
+        "chat_rate_limited_total",
+        "counter",
+        "Rate limiter actions",
+        vec![
+            (label("action", "dropped"), load(&limiter.dropped)),
+            (label("action", "warned"), load(&limiter.warned)),
+            (label("action", "muted"), load(&limiter.muted)),
+            (label("action", "disconnected"), load(&limiter.disconnected)),
+        ],
+    );
+    let mut commands: Vec<_> = metrics.commands.iter().map(|entry| (*entry.key(), *entry.value())).collect();
+    commands.sort();
+    metric(
+        "chat_commands_total",
+        "counter",
+        "Commands received by type",
+        commands.into_iter().map(|(command, count)| (label("command", command), count)).collect(),
+    );
+    let mut errors: Vec<_> = metrics
+        .errors
+        .iter()
+        .map(|entry| (format!("{:?}", entry.key()), *entry.value()))
+        .collect();
+    errors.sort();
+    metric(
+        "chat_connection_errors_total",
+        "counter",
+        "Connection errors by io::ErrorKind",
+        errors.into_iter().map(|(kind, count)| (label("kind", &kind), count)).collect(),
+    );
+    out
+}
+
+fn label(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("{{{name}=\"{value}\"}}")
+}
+
+// serves /metrics for prometheus and /healthz for
+// liveness checks, one request per connection
+pub async fn handle_metrics<S>(mut conn: S, shared: Shared)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut conn)).await {
+        Ok(Ok(head)) => head,
+        Ok(Err(err)) => {
+            tracing::debug!("bad metrics request: {err}");
+            return;
+        }
+        Err(_) => return,
+    };
+    let (status, content_type, body) = match request_line(&head) {
+        (Some("GET"), Some("/metrics")) => (OK, PROMETHEUS_CONTENT_TYPE, render_metrics(&shared)),
+        (Some("GET"), Some("/healthz")) => (OK, "text/plain", "ok\n".to_owned()),
+        (Some("GET"), _) => (NOT_FOUND, "text/plain", "Not found\n".to_owned()),
+        _ => (METHOD_NOT_ALLOWED, "text/plain", "Method not allowed\n".to_owned()),
+    };
+    if let Err(err) = write_response(&mut conn, status, content_type, &body).await {
+        tracing::debug!("failed to serve metrics: {err}");
+    }
+}
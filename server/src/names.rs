@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use compact_str::CompactString;
+use dashmap::DashSet;
+use crate::NameGenerator;
+
+#[derive(Clone)]
+#[repr(transparent)]
+pub struct Names(Arc<DashSet<CompactString>>);
+
+impl Names {
+    pub fn new() -> Self {
+        Self(Arc::new(DashSet::with_capacity(32)))
+    }
+    pub fn insert(&self, name: CompactString) -> bool {
+        self.0.insert(name)
+    }
+    pub fn remove(&self, name: &str) -> bool {
+        self.0.remove(name).is_some()
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn get_unique(&self, name_generator: &mut NameGenerator) -> CompactString {
+        let mut name = addressable(name_generator.next());
+        while !self.0.insert(name.clone()) {
+            name = addressable(name_generator.next());
+        }
+        name
+    }
+    // claims `next` before releasing `prev` so that
+    // the user always holds at least one of the two
+    // names and nobody can sneak in between
+    pub fn rename(&self, prev: &str, next: CompactString) -> bool {
+        if !self.0.insert(next) {
+            return false;
+        }
+        self.0.remove(prev);
+        true
+    }
+}
+
+// some generated names have spaces or punctuation in
+// them, drop those so guests can be reached with /msg
+// and the name passes `valid_name`
+fn addressable(name: CompactString) -> CompactString {
+    name.chars().filter(char::is_ascii_alphanumeric).collect()
+}
+
+impl Default for Names {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::ServerConfig;
+
+pub const CHAT_BURST: u32 = 10;
+pub const CHAT_REFILL_MS: u64 = 500;
+pub const COMMAND_BURST: u32 = 10;
+pub const COMMAND_REFILL_MS: u64 = 1000;
+pub const FLOOD_MUTE_SECS: u64 = 30;
+// strikes are forgiven after this long without a new one
+const STRIKE_RESET: Duration = Duration::from_secs(60);
+
+// holds up to `burst` tokens and earns one back
+// every `refill`, each message takes one
+pub struct TokenBucket {
+    burst: u32,
+    refill: Duration,
+    tokens: u32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(burst: u32, refill: Duration, now: Instant) -> Self {
+        Self {
+            burst,
+            refill,
+            tokens: burst,
+            last_refill: now,
+        }
+    }
+
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        // capped before narrowing, a long idle can earn
+        // far more than fits in a u32
+        let earned = (elapsed.as_nanos() / self.refill.as_nanos().max(1)).min(u128::from(self.burst)) as u32;
+        if self.tokens.saturating_add(earned) >= self.burst {
+            self.tokens = self.burst;
+            self.last_refill = now;
+        } else if earned > 0 {
+            // keep the remainder so slow trickles still add up
+            self.tokens += earned;
+            self.last_refill += self.refill * earned;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traffic {
+    Chat,
+    Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Warn,
+    // just got muted for this long
+    Mute(Duration),
+    // still muted for this long
+    Muted(Duration),
+    Disconnect,
+}
+
+// shared by every session, for reporting
+#[derive(Debug, Default)]
+pub struct LimiterStats {
+    pub dropped: AtomicU64,
+    pub warned: AtomicU64,
+    pub muted: AtomicU64,
+    pub disconnected: AtomicU64,
+}
+
+// one per session, chat and commands are limited separately
+// and going over either limit escalates from a warning, to
+// a mute which only silences chat, to a disconnect
+pub struct RateLimiter {
+    chat: TokenBucket,
+    commands: TokenBucket,
+    mute: Duration,
+    strikes: u32,
+    last_strike: Option<Instant>,
+    muted_until: Option<Instant>,
+    stats: Arc<LimiterStats>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &ServerConfig, stats: Arc<LimiterStats>) -> Self {
+        let now = Instant::now();
+        Self {
+            chat: TokenBucket::new(config.chat_burst, Duration::from_millis(config.chat_refill_ms), now),
+            commands: TokenBucket::new(config.command_burst, Duration::from_millis(config.command_refill_ms), now),
+            mute: Duration::from_secs(config.flood_mute_secs),
+            strikes: 0,
+            last_strike: None,
+            muted_until: None,
+            stats,
+        }
+    }
+
+    pub fn check(&mut self, traffic: Traffic, now: Instant) -> Verdict {
+        if traffic == Traffic::Chat && let Some(until) = self.muted_until.filter(|until| now < *until) {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return Verdict::Muted(until - now);
+        }
+        let bucket = match traffic {
+            Traffic::Chat => &mut self.chat,
+            Traffic::Command => &mut self.commands,
+        };
+        if bucket.try_take(now) {
+            return Verdict::Allow;
+        }
+        self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        if self.last_strike.is_some_and(|last| now.saturating_duration_since(last) > STRIKE_RESET) {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = Some(now);
+        match self.strikes {
+            1 => {
+                self.stats.warned.fetch_add(1, Ordering::Relaxed);
+                Verdict::Warn
+            }
+            2 => {
+                self.stats.muted.fetch_add(1, Ordering::Relaxed);
+                self.muted_until = Some(now + self.mute);
+                Verdict::Mute(self.mute)
+            }
+            _ => {
+                self.stats.disconnected.fetch_add(1, Ordering::Relaxed);
+                Verdict::Disconnect
+            }
+        }
+    }
+}
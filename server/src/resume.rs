@@ -0,0 +1,65 @@
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use compact_str::CompactString;
+use dashmap::DashMap;
+use crate::session::Session;
+
+pub const RESUME_GRACE_SECS: u64 = 60;
+
+// sessions whose connection dropped, by resume token. a parked
+// session stays in its room with its name, and its receivers
+// keep collecting room and direct messages, until it's resumed
+// or the grace period runs out
+#[derive(Clone)]
+#[repr(transparent)]
+pub struct Resumes(Arc<DashMap<CompactString, Session>>);
+
+impl Resumes {
+    pub fn new() -> Self {
+        Self(Arc::new(DashMap::new()))
+    }
+
+    // as good as a password while the session is
+    // parked, so it comes from the os rng
+    pub fn token() -> CompactString {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        let mut token = CompactString::default();
+        for byte in bytes {
+            let _ = write!(token, "{byte:02x}");
+        }
+        token
+    }
+
+    pub(crate) fn park(&self, token: CompactString, session: Session, grace: Duration) {
+        self.0.insert(token.clone(), session);
+        let resumes = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            // dropping the session leaves the room and releases
+            // the name, unless it was resumed in the meantime
+            resumes.0.remove(&token);
+        });
+    }
+
+    // tokens only work once
+    pub(crate) fn take(&self, token: &str) -> Option<Session> {
+        self.0.remove(token).map(|(_, session)| session)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Default for Resumes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
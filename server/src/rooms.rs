@@ -0,0 +1,576 @@
+use std::{collections::{HashMap, HashSet}, fmt, net::IpAddr, sync::Arc, time::{Duration, Instant}};
+use chat_protocol::{ModeChange, Role, RoomInfo, RoomModes, Span, UserInfo, WhoisInfo};
+use compact_str::CompactString;
+use dashmap::DashMap;
+use tokio::sync::broadcast::{self, Sender};
+use crate::{unix_millis, MessageStore};
+
+pub const ROOM_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Clone)]
+pub enum RoomMsg {
+    Joined(CompactString),
+    Left(CompactString),
+    Renamed {
+        from: CompactString,
+        to: CompactString,
+    },
+    // spans and mentions are worked out once by the sender
+    Msg {
+        from: CompactString,
+        text: Arc<str>,
+        spans: Arc<[Span]>,
+        action: bool,
+        mentioned: Arc<[CompactString]>,
+    },
+    RoleChanged {
+        user: CompactString,
+        role: Role,
+        by: CompactString,
+    },
+    // the kicked user's own session moves them out
+    Kicked {
+        user: CompactString,
+        by: CompactString,
+        reason: Option<Arc<str>>,
+    },
+    TopicChanged {
+        by: CompactString,
+        topic: Arc<str>,
+    },
+    ModesChanged {
+        by: CompactString,
+        modes: RoomModes,
+    },
+    ShuttingDown(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    Banned,
+    InviteOnly,
+    BadKey,
+    Full,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Banned => f.write_str("you are banned"),
+            Self::InviteOnly => f.write_str("it is invite only"),
+            Self::BadKey => f.write_str("it needs the right key"),
+            Self::Full => f.write_str("it is full"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModError {
+    NotAllowed,
+    NotInRoom(CompactString),
+    NotBanned(CompactString),
+    NotInvited(CompactString),
+}
+
+impl fmt::Display for ModError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAllowed => f.write_str("You are not allowed to do that"),
+            Self::NotInRoom(user) => write!(f, "{user} is not in this room"),
+            Self::NotBanned(target) => write!(f, "{target} is not banned"),
+            Self::NotInvited(user) => write!(f, "{user} is not invited"),
+        }
+    }
+}
+
+// a user's presence in a room, the only record of who is
+// where, so counts and listings can't disagree with it
+struct Member {
+    role: Role,
+    ip: IpAddr,
+    // unix time in millis
+    joined: u64,
+    last_chat: Option<Instant>,
+    profile: Profile,
+}
+
+// the part of a member that follows the user between rooms
+#[derive(Clone)]
+struct Profile {
+    connected: Instant,
+    last_active: Instant,
+    // empty when away without a message
+    away: Option<Arc<str>>,
+    client: CompactString,
+}
+
+impl Member {
+    fn new(role: Role, ip: IpAddr) -> Self {
+        let now = Instant::now();
+        Self {
+            role,
+            ip,
+            joined: unix_millis(),
+            last_chat: None,
+            profile: Profile {
+                connected: now,
+                last_active: now,
+                away: None,
+                client: CompactString::default(),
+            },
+        }
+    }
+}
+
+pub struct Room {
+    tx: Sender<RoomMsg>,
+    users: HashMap<CompactString, Member>,
+    // bans only last as long as the room does
+    banned_names: HashSet<CompactString>,
+    banned_ips: HashSet<IpAddr>,
+    // invited users skip the key and invite only checks
+    invited: HashSet<CompactString>,
+    key: Option<Arc<str>>,
+    topic: Option<Arc<str>>,
+    creator: CompactString,
+    // unix time in millis
+    created: u64,
+    modes: RoomModes,
+    // was ever invite only, keyed or secret, so what was
+    // said in it goes with it rather than to whoever
+    // next creates a room by the same name
+    private: bool,
+}
+
+impl Room {
+    fn new(capacity: usize, creator: &str) -> Self {
+        let (tx,_) = broadcast::channel(capacity);
+        let users = HashMap::with_capacity(8);
+        Self {
+            tx,
+            users,
+            banned_names: HashSet::new(),
+            banned_ips: HashSet::new(),
+            invited: HashSet::new(),
+            key: None,
+            topic: None,
+            creator: creator.into(),
+            created: unix_millis(),
+            modes: RoomModes::default(),
+            private: false,
+        }
+    }
+
+    fn role(&self, user_name: &str) -> Role {
+        self.users.get(user_name).map(|member| member.role).unwrap_or(Role::Member)
+    }
+
+    // operators and up moderate anyone ranked below them
+    fn can_moderate(&self, actor: &str, target_role: Role) -> bool {
+        let actor = self.role(actor);
+        actor >= Role::Operator && actor > target_role
+    }
+}
+
+#[derive(Clone)]
+pub struct Rooms {
+    rooms: Arc<DashMap<CompactString, Room>>,
+    channel_capacity: usize,
+    // cleared of private rooms' history as they go away
+    store: Option<Arc<dyn MessageStore>>,
+    // everyone's room, so nobody gets to run it
+    main_room: Option<CompactString>,
+}
+
+impl Rooms {
+    pub fn new() -> Self {
+        Self::with_channel_capacity(ROOM_CHANNEL_CAPACITY)
+    }
+
+    pub fn with_channel_capacity(channel_capacity: usize) -> Self {
+        Self {
+            rooms: Arc::new(DashMap::new()),
+            channel_capacity,
+            store: None,
+            main_room: None,
+        }
+    }
+
+    pub fn with_store(self, store: Arc<dyn MessageStore>) -> Self {
+        Self { store: Some(store), ..self }
+    }
+
+    pub fn with_main_room(self, main_room: &str) -> Self {
+        Self { main_room: Some(main_room.into()), ..self }
+    }
+
+    // whoever creates the room owns it, except for main
+    pub fn join(
+        &self,
+        room_name: &str,
+        user_name: &str,
+        ip: IpAddr,
+        key: Option<&str>,
+    ) -> Result<Sender<RoomMsg>, JoinError> {
+        let mut room = self
+            .rooms
+            .entry(room_name.into())
+            .or_insert_with(|| Room::new(self.channel_capacity, user_name));
+        if room.banned_names.contains(user_name) || room.banned_ips.contains(&ip) {
+            return Err(JoinError::Banned);
+        }
+        if room.modes.max_users.is_some_and(|max_users| room.users.len() >= max_users) {
+            return Err(JoinError::Full);
+        }
+        if !room.invited.contains(user_name) {
+            if room.modes.invite_only {
+                return Err(JoinError::InviteOnly);
+            }
+            if room.key.is_some() && room.key.as_deref() != key {
+                return Err(JoinError::BadKey);
+            }
+        }
+        let is_main = self.main_room.as_deref() == Some(room_name);
+        let role = if room.users.is_empty() && !is_main { Role::Owner } else { Role::Member };
+        room.users.insert(user_name.into(), Member::new(role, ip));
+        Ok(room.tx.clone())
+    }
+
+    // the room goes away with its last user, all under the
+    // lock joins take, so no one joins a dead room and no
+    // one ever sees an empty one
+    pub fn leave(&self, room_name: &str, user_name: &str) {
+        let removed = self.rooms.remove_if_mut(room_name, |_, room| {
+            room.users.remove(user_name);
+            room.users.is_empty()
+        });
+        if let (Some((_, room)), Some(store)) = (removed, &self.store)
+            && room.private
+        {
+            store.clear(room_name);
+        }
+    }
+
+    // joins the next room before leaving the previous
+    // one, so a failed join leaves the user where they were
+    pub fn change(
+        &self,
+        prev_room: &str,
+        next_room: &str,
+        user_name: &str,
+        ip: IpAddr,
+        key: Option<&str>,
+    ) -> Result<Sender<RoomMsg>, JoinError> {
+        let tx = self.join(next_room, user_name, ip, key)?;
+        let profile = self.rooms.get(prev_room).and_then(|room| Some(room.users.get(user_name)?.profile.clone()));
+        if let Some(profile) = profile {
+            self.update(next_room, user_name, |member| member.profile = profile);
+        }
+        self.leave(prev_room, user_name);
+        Ok(tx)
+    }
+
+    pub fn change_name(&self, room_name: &str, prev_name: &str, next_name: &str) {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return;
+        };
+        if let Some(member) = room.users.remove(prev_name) {
+            room.users.insert(next_name.into(), member);
+        }
+    }
+
+    pub fn role(&self, room_name: &str, user_name: &str) -> Option<Role> {
+        let room = self.rooms.get(room_name)?;
+        room.users.get(user_name).map(|member| member.role)
+    }
+
+    // nobody can hand out a role as high as their own,
+    // so only owners make operators
+    pub fn set_role(&self, room_name: &str, actor: &str, user_name: &str, role: Role) -> Result<(), ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotInRoom(user_name.into()));
+        };
+        let Some(current) = room.users.get(user_name).map(|member| member.role) else {
+            return Err(ModError::NotInRoom(user_name.into()));
+        };
+        if !room.can_moderate(actor, current) || role >= room.role(actor) {
+            return Err(ModError::NotAllowed);
+        }
+        if let Some(member) = room.users.get_mut(user_name) {
+            member.role = role;
+        }
+        Ok(())
+    }
+
+    pub fn can_kick(&self, room_name: &str, actor: &str, user_name: &str) -> Result<(), ModError> {
+        let room = self.rooms.get(room_name);
+        let Some(role) = room.as_ref().and_then(|room| room.users.get(user_name)).map(|member| member.role) else {
+            return Err(ModError::NotInRoom(user_name.into()));
+        };
+        match room {
+            Some(room) if room.can_moderate(actor, role) => Ok(()),
+            _ => Err(ModError::NotAllowed),
+        }
+    }
+
+    // bans a name or an ip address, returning the users
+    // currently in the room that the ban applies to
+    pub fn ban(&self, room_name: &str, actor: &str, target: &str) -> Result<Vec<CompactString>, ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotAllowed);
+        };
+        let ip = target.parse::<IpAddr>().ok();
+        let matches = |name: &CompactString, member: &Member| match ip {
+            Some(ip) => member.ip == ip,
+            None => name == target,
+        };
+        if !room.can_moderate(actor, Role::Member) {
+            return Err(ModError::NotAllowed);
+        }
+        // an ip ban can't be used to get rid of an equal,
+        // though it may cover the actor's own address
+        if room
+            .users
+            .iter()
+            .any(|(name, member)| matches(name, member) && name != actor && !room.can_moderate(actor, member.role))
+        {
+            return Err(ModError::NotAllowed);
+        }
+        match ip {
+            Some(ip) => room.banned_ips.insert(ip),
+            None => room.banned_names.insert(target.into()),
+        };
+        Ok(room
+            .users
+            .iter()
+            .filter(|(name, member)| matches(name, member) && *name != actor)
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+
+    pub fn unban(&self, room_name: &str, actor: &str, target: &str) -> Result<(), ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotAllowed);
+        };
+        if !room.can_moderate(actor, Role::Member) {
+            return Err(ModError::NotAllowed);
+        }
+        let removed = match target.parse::<IpAddr>() {
+            Ok(ip) => room.banned_ips.remove(&ip),
+            Err(_) => room.banned_names.remove(target),
+        };
+        if !removed {
+            return Err(ModError::NotBanned(target.into()));
+        }
+        Ok(())
+    }
+
+    pub fn topic(&self, room_name: &str) -> Option<Arc<str>> {
+        self.rooms.get(room_name)?.topic.clone()
+    }
+
+    pub fn set_topic(&self, room_name: &str, actor: &str, topic: Arc<str>) -> Result<(), ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotAllowed);
+        };
+        if room.role(actor) < Role::Operator {
+            return Err(ModError::NotAllowed);
+        }
+        room.topic = Some(topic);
+        Ok(())
+    }
+
+    pub fn modes(&self, room_name: &str) -> RoomModes {
+        self.rooms.get(room_name).map(|room| room.modes).unwrap_or_default()
+    }
+
+    pub fn set_mode(&self, room_name: &str, actor: &str, change: ModeChange) -> Result<RoomModes, ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotAllowed);
+        };
+        if room.role(actor) < Role::Operator {
+            return Err(ModError::NotAllowed);
+        }
+        if let ModeChange::Key(key) = &change {
+            room.key = key.as_deref().map(Arc::from);
+        }
+        room.modes.apply(change);
+        room.private |= room.modes.invite_only || room.modes.keyed || room.modes.secret;
+        Ok(room.modes)
+    }
+
+    pub fn invite(&self, room_name: &str, actor: &str, user_name: &str) -> Result<(), ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotAllowed);
+        };
+        if room.role(actor) < Role::Operator {
+            return Err(ModError::NotAllowed);
+        }
+        room.invited.insert(user_name.into());
+        Ok(())
+    }
+
+    pub fn uninvite(&self, room_name: &str, actor: &str, user_name: &str) -> Result<(), ModError> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Err(ModError::NotAllowed);
+        };
+        if room.role(actor) < Role::Operator {
+            return Err(ModError::NotAllowed);
+        }
+        if !room.invited.remove(user_name) {
+            return Err(ModError::NotInvited(user_name.into()));
+        }
+        Ok(())
+    }
+
+    // in moderated rooms only voiced users and up talk
+    pub fn can_speak(&self, room_name: &str, user_name: &str) -> bool {
+        match self.rooms.get(room_name) {
+            Some(room) => !room.modes.moderated || room.role(user_name) >= Role::Voice,
+            None => true,
+        }
+    }
+
+    // in slow mode members wait between messages, operators
+    // don't, the error is how much longer they have to wait
+    pub fn check_slow_mode(&self, room_name: &str, user_name: &str, now: Instant) -> Result<(), Duration> {
+        let Some(mut room) = self.rooms.get_mut(room_name) else {
+            return Ok(());
+        };
+        let slow_mode = room.modes.slow_secs.map(Duration::from_secs);
+        let Some(member) = room.users.get_mut(user_name) else {
+            return Ok(());
+        };
+        if let (Some(slow_mode), Some(last_chat)) = (slow_mode, member.last_chat) {
+            let waited = now.saturating_duration_since(last_chat);
+            if member.role < Role::Operator && waited < slow_mode {
+                return Err(slow_mode - waited);
+            }
+        }
+        member.last_chat = Some(now);
+        Ok(())
+    }
+
+    // secret rooms are only listed to their own members
+    pub fn list(&self, viewer: &str) -> Vec<RoomInfo> {
+        let mut list: Vec<_> = self
+            .rooms
+            .iter()
+            .filter(|room| !room.modes.secret || room.users.contains_key(viewer))
+            .map(|room| RoomInfo {
+                name: room.key().to_string(),
+                users: room.users.len(),
+                topic: room.topic.as_deref().map(String::from),
+                creator: room.creator.to_string(),
+                created: room.created,
+                modes: room.modes,
+            })
+            .collect();
+        list.sort_by(|a, b| {
+            use std::cmp::Ordering;
+            match b.users.cmp(&a.users) {
+                Ordering::Equal => a.name.cmp(&b.name),
+                ordering => ordering,
+            }
+        });
+        list
+    }
+
+    // sent to every room, returns how many rooms got it
+    pub fn broadcast(&self, msg: RoomMsg) -> usize {
+        self.rooms.iter().filter(|room| room.tx.send(msg.clone()).is_ok()).count()
+    }
+
+    // every room including secret ones, sorted by name
+    pub fn receiver_counts(&self) -> Vec<(CompactString, usize)> {
+        let mut counts: Vec<_> = self
+            .rooms
+            .iter()
+            .map(|room| (room.key().clone(), room.tx.receiver_count()))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    fn update(&self, room_name: &str, user_name: &str, update: impl FnOnce(&mut Member)) {
+        if let Some(mut room) = self.rooms.get_mut(room_name)
+            && let Some(member) = room.users.get_mut(user_name)
+        {
+            update(member);
+        }
+    }
+
+    // anything the user sent counts as activity and brings
+    // them back if they were away, returns whether they were
+    pub fn touch(&self, room_name: &str, user_name: &str, now: Instant) -> bool {
+        let mut was_away = false;
+        self.update(room_name, user_name, |member| {
+            member.profile.last_active = now;
+            was_away = member.profile.away.take().is_some();
+        });
+        was_away
+    }
+
+    // an empty message is away without one
+    pub fn set_away(&self, room_name: &str, user_name: &str, away: Arc<str>, now: Instant) {
+        self.update(room_name, user_name, |member| {
+            member.profile.last_active = now;
+            member.profile.away = Some(away);
+        });
+    }
+
+    // only looks in the given room, it's
+    // for users mentioned in a message there
+    pub fn away(&self, room_name: &str, user_name: &str) -> Option<Arc<str>> {
+        self.rooms.get(room_name)?.users.get(user_name)?.profile.away.clone()
+    }
+
+    // how the user is connected, like "tcp/text"
+    pub fn set_client(&self, room_name: &str, user_name: &str, client: &str) {
+        self.update(room_name, user_name, |member| member.profile.client = client.into());
+    }
+
+    // finds the user in whichever room they are
+    pub fn whois(&self, user_name: &str, viewer: &str, now: Instant) -> Option<WhoisInfo> {
+        self.rooms.iter().find_map(|room| {
+            let member = room.users.get(user_name)?;
+            let visible = !room.modes.secret || room.users.contains_key(viewer);
+            let profile = &member.profile;
+            Some(WhoisInfo {
+                name: user_name.into(),
+                room: visible.then(|| room.key().to_string()),
+                client: profile.client.to_string(),
+                online_secs: now.saturating_duration_since(profile.connected).as_secs(),
+                idle_secs: now.saturating_duration_since(profile.last_active).as_secs(),
+                away: profile.away.as_deref().map(String::from),
+            })
+        })
+    }
+
+    // sorted by name, empty if the room is gone
+    pub fn list_users(&self, room_name: &str, now: Instant) -> Vec<UserInfo> {
+        let Some(room) = self.rooms.get(room_name) else {
+            return Vec::new();
+        };
+        let mut users: Vec<_> = room
+            .users
+            .iter()
+            .map(|(name, member)| UserInfo {
+                name: name.to_string(),
+                role: member.role,
+                joined: member.joined,
+                idle_secs: now.saturating_duration_since(member.profile.last_active).as_secs(),
+                away: member.profile.away.as_deref().map(String::from),
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        users
+    }
+
+}
+
+impl Default for Rooms {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,268 @@
+use std::future::{self, Future};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use futures::{stream, SinkExt, StreamExt};
+use tokio::{io::{AsyncRead, AsyncWrite}, net::TcpListener, sync::Semaphore, task::JoinSet};
+use tokio_util::{codec::{FramedWrite, LinesCodec}, sync::CancellationToken};
+use tracing::Instrument;
+use crate::{
+    handle_lines, handle_metrics, handle_websocket, open_store, tls_acceptor, Accounts, Inboxes, LimiterStats,
+    MessageStore, Metrics, NameGenerator, Names, Peer, Resumes, RoomMsg, Rooms, ServerConfig, User,
+};
+
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const SHUTDOWN_GRACE_SECS: u64 = 5;
+pub const SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+// waits between accepts after an error, doubling up to the max
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+// everything sessions share, cheap to clone
+#[derive(Clone)]
+pub struct Shared {
+    pub names: Names,
+    pub rooms: Rooms,
+    pub inboxes: Inboxes,
+    pub resumes: Resumes,
+    pub store: Arc<dyn MessageStore>,
+    pub accounts: Accounts,
+    pub limiter_stats: Arc<LimiterStats>,
+    pub metrics: Arc<Metrics>,
+    // cancelled once the grace period of a shutdown is over
+    pub shutdown: CancellationToken,
+    pub config: Arc<ServerConfig>,
+}
+
+impl Shared {
+    pub fn new(config: ServerConfig) -> Result<Self, io::Error> {
+        let store = open_store(&config)?;
+        Ok(Self {
+            names: Names::new(),
+            rooms: Rooms::with_channel_capacity(config.room_channel_capacity)
+                .with_store(store.clone())
+                .with_main_room(&config.main_room),
+            inboxes: Inboxes::new(),
+            resumes: Resumes::new(),
+            store,
+            accounts: match &config.accounts_path {
+                Some(path) => Accounts::open(path)?,
+                None => Accounts::new(),
+            },
+            limiter_stats: Arc::new(LimiterStats::default()),
+            metrics: Arc::new(Metrics::default()),
+            shutdown: CancellationToken::new(),
+            config: Arc::new(config),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Transport {
+    Lines,
+    WebSocket,
+    Metrics,
+}
+
+impl Transport {
+    // how users are connected, as shown by /whois
+    fn label(self, tls: bool) -> &'static str {
+        match (self, tls) {
+            (Transport::Lines, false) => "tcp",
+            (Transport::Lines, true) => "tls",
+            (Transport::WebSocket, false) => "ws",
+            (Transport::WebSocket, true) => "wss",
+            (Transport::Metrics, _) => "http",
+        }
+    }
+}
+
+pub async fn serve(
+    listeners: Vec<TcpListener>,
+    ws_listeners: Vec<TcpListener>,
+    metrics_listeners: Vec<TcpListener>,
+    config: ServerConfig,
+) -> Result<(), io::Error> {
+    serve_with_shutdown(listeners, ws_listeners, metrics_listeners, config, future::pending()).await
+}
+
+// stops accepting once `shutdown` completes, then
+// drains the open sessions before returning
+pub async fn serve_with_shutdown(
+    listeners: Vec<TcpListener>,
+    ws_listeners: Vec<TcpListener>,
+    metrics_listeners: Vec<TcpListener>,
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), io::Error> {
+    let shared = Shared::new(config)?;
+    let config = shared.config.clone();
+    let connections = Arc::new(Semaphore::new(config.max_connections));
+    let mut name_generator = NameGenerator::new();
+    let acceptor = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls_acceptor(cert, key)?),
+        _ => None,
+    };
+    let listeners = listeners
+        .into_iter()
+        .map(|server| (server, Transport::Lines))
+        .chain(ws_listeners.into_iter().map(|server| (server, Transport::WebSocket)))
+        .chain(metrics_listeners.into_iter().map(|server| (server, Transport::Metrics)));
+    // merge all listeners into one stream so there's
+    // a single accept loop and a single name generator
+    let mut incoming = stream::select_all(listeners.map(|listener| {
+        Box::pin(stream::unfold(listener, |(server, transport)| async move {
+            let accepted = server.accept().await;
+            Some(((accepted, transport), (server, transport)))
+        }))
+    }));
+    let mut sessions = JoinSet::new();
+    let mut shutdown = pin!(shutdown);
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+    let mut fatal = None;
+    loop {
+        let (accepted, transport) = tokio::select! {
+            _ = &mut shutdown => break,
+            // reap finished sessions so the set doesn't keep growing
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => continue,
+            next = incoming.next() => match next {
+                Some(next) => next,
+                None => break,
+            },
+        };
+        let (mut tcp, peer) = match accepted {
+            Ok(accepted) => {
+                backoff = ACCEPT_BACKOFF_MIN;
+                accepted
+            }
+            // only a broken listener stops the server, and even
+            // then the sessions are drained like any shutdown
+            Err(err) if is_fatal(&err) => {
+                tracing::error!("failed to accept connection, shutting down: {err}");
+                shared.metrics.error(err.kind());
+                fatal = Some(err);
+                break;
+            }
+            // like running out of fds or a client giving up
+            // mid-handshake, give it a moment and carry on
+            Err(err) => {
+                tracing::warn!("failed to accept connection: {err}");
+                shared.metrics.error(err.kind());
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    _ = tokio::time::sleep(backoff) => (),
+                }
+                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                continue;
+            }
+        };
+        // scrapes don't count as users or take a connection slot
+        if let Transport::Metrics = transport {
+            sessions.spawn(handle_metrics(tcp, shared.clone()));
+            continue;
+        }
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            tracing::warn!(%peer, "rejected connection, server is full");
+            tokio::spawn(async move {
+                let mut sink = FramedWrite::new(&mut tcp, LinesCodec::new());
+                let _ = sink.send("Server is full, try again later").await;
+            });
+            continue;
+        };
+        let mut user = User::new(shared.names.clone(), shared.names.get_unique(&mut name_generator));
+        // guests never get a name somebody registered,
+        // the replaced claim is released as it's dropped
+        while shared.accounts.is_registered(user.name()) {
+            user = User::new(shared.names.clone(), shared.names.get_unique(&mut name_generator));
+        }
+        let span = tracing::info_span!(
+            "session",
+            %peer,
+            name = %user.name(),
+            room = %config.main_room,
+        );
+        let (shared, acceptor) = (shared.clone(), acceptor.clone());
+        sessions.spawn(async move {
+            tracing::info!("connected");
+            shared.metrics.connections.fetch_add(1, Ordering::Relaxed);
+            shared.metrics.connections_total.fetch_add(1, Ordering::Relaxed);
+            let metrics = shared.metrics.clone();
+            match acceptor {
+                Some(acceptor) => {
+                    let handshake = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await;
+                    match handshake {
+                        Ok(Ok(tls)) => {
+                            let peer = Peer { addr: peer, transport: transport.label(true) };
+                            handle_transport(tls, transport, shared, user, peer).await
+                        }
+                        Ok(Err(err)) => {
+                            tracing::warn!("tls handshake failed: {err}");
+                            shared.metrics.error(err.kind());
+                        }
+                        Err(_) => {
+                            tracing::warn!("tls handshake timed out");
+                            shared.metrics.error(io::ErrorKind::TimedOut);
+                        }
+                    }
+                }
+                None => {
+                    let peer = Peer { addr: peer, transport: transport.label(false) };
+                    handle_transport(tcp, transport, shared, user, peer).await
+                }
+            }
+            tracing::info!("disconnected");
+            metrics.connections.fetch_sub(1, Ordering::Relaxed);
+            drop(permit);
+        }.instrument(span));
+    }
+    // stop accepting right away
+    drop(incoming);
+    drain(&shared, sessions).await;
+    match fatal {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// the listener itself is unusable, as opposed to errors
+// about one connection or a shortage of fds or memory
+fn is_fatal(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported)
+}
+
+// users get a warning and the grace period to wrap up,
+// then sessions are told to close, and the ones that
+// haven't by the timeout are aborted
+async fn drain(shared: &Shared, mut sessions: JoinSet<()>) {
+    let grace = Duration::from_secs(shared.config.shutdown_grace_secs);
+    let timeout = Duration::from_secs(shared.config.shutdown_timeout_secs);
+    tracing::info!("shutting down {} sessions in {}s", sessions.len(), grace.as_secs());
+    shared.rooms.broadcast(RoomMsg::ShuttingDown(grace));
+    // no need to wait out the grace period if everybody left
+    let _ = tokio::time::timeout(grace, async { while sessions.join_next().await.is_some() {} }).await;
+    shared.shutdown.cancel();
+    let closed = tokio::time::timeout(timeout, async { while sessions.join_next().await.is_some() {} }).await;
+    if closed.is_err() {
+        tracing::warn!("aborting {} sessions that didn't close in time", sessions.len());
+        sessions.shutdown().await;
+    }
+    if let Err(err) = shared.store.flush() {
+        tracing::error!("failed to flush history: {err}");
+    }
+    tracing::info!("shut down");
+}
+
+async fn handle_transport<S>(conn: S, transport: Transport, shared: Shared, user: User, peer: Peer)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    match transport {
+        Transport::Lines => handle_lines(conn, shared, user, peer).await,
+        Transport::WebSocket => handle_websocket(conn, shared, user, peer).await,
+        // handed off before a name is picked
+        Transport::Metrics => unreachable!(),
+    }
+}
@@ -0,0 +1,1221 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use chat_protocol::{markup, ClientFrame, Format, HistoryEntry, ModeChange, ServerFrame, PROTOCOL_VERSION};
+use compact_str::{format_compact, CompactString};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio::{io::{AsyncRead, AsyncWrite}, sync::{broadcast::{self, error::RecvError}, mpsc}};
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec, LinesCodecError};
+use crate::{
+    valid_name, Accounts, DeliveryError, DirectMsg, InboxMsg, Inboxes, MessageStore, Names, RateLimiter,
+    Resumes, RoomMsg, Rooms, ServerConfig, Shared, StoredMsg, Traffic, Verdict,
+};
+
+pub const MAIN: &str = "main";
+pub const HELP_MSG: &str = include_str!("help.txt");
+pub const MAX_MSG_LEN: usize = 400;
+const RESUME_EXPIRED: &str = "Can't resume, the session has expired";
+// json frames carry some overhead on top of the
+// message itself, and escaping can double its size
+const MAX_FRAME_OVERHEAD: usize = 100;
+
+// writes frames to the user in whichever format
+// they negotiated, plain text by default
+struct FrameSink<O> {
+    sink: O,
+    format: Format,
+}
+
+impl<O: Sink<String, Error = LinesCodecError> + Unpin> FrameSink<O> {
+    async fn send(&mut self, me: &str, frame: &ServerFrame) -> Result<(), LinesCodecError> {
+        let line = match self.format {
+            Format::Text => frame.to_text(me),
+            Format::Json => serde_json::to_string(frame).expect("server frames always serialize"),
+        };
+        self.sink.send(line).await
+    }
+}
+
+// the other end of a session
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    // "tcp", "tls", "ws" or "wss"
+    pub transport: &'static str,
+}
+
+impl Peer {
+    // like "tcp/text", shown by /whois
+    fn client(&self, format: Format) -> CompactString {
+        format_compact!("{}/{}", self.transport, format.as_str())
+    }
+}
+
+// why a session ended other than the user leaving,
+// failed writes included so they can't be ignored
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    LineTooLong,
+}
+
+// the usual ways for a client to just go away
+const DISCONNECT_KINDS: [ErrorKind; 4] = [
+    ErrorKind::BrokenPipe,
+    ErrorKind::ConnectionReset,
+    ErrorKind::ConnectionAborted,
+    ErrorKind::UnexpectedEof,
+];
+
+impl SessionError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(err) => err.kind(),
+            Self::LineTooLong => ErrorKind::InvalidData,
+        }
+    }
+
+    // not worth more than an info log
+    pub fn is_disconnect(&self) -> bool {
+        DISCONNECT_KINDS.contains(&self.kind())
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "connection error: {err}"),
+            Self::LineTooLong => f.write_str("line too long"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::LineTooLong => None,
+        }
+    }
+}
+
+impl From<io::Error> for SessionError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<LinesCodecError> for SessionError {
+    fn from(err: LinesCodecError) -> Self {
+        match err {
+            LinesCodecError::Io(err) => Self::Io(err),
+            LinesCodecError::MaxLineLengthExceeded => Self::LineTooLong,
+        }
+    }
+}
+
+// a claim on a name, released on drop, taken as soon
+// as a connection is accepted so every way out of the
+// handshakes gives the name back
+pub struct User {
+    names: Names,
+    name: CompactString,
+}
+
+impl User {
+    // `name` must already be claimed in `names`
+    pub fn new(names: Names, name: CompactString) -> Self {
+        Self { names, name }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for User {
+    fn drop(&mut self) {
+        self.names.remove(&self.name);
+    }
+}
+
+// a user that is in a room and has an inbox. dropping it
+// leaves both and then releases the name, so however the
+// session ends, errors and panics included, no ghost is left
+pub(crate) struct Session {
+    user: User,
+    rooms: Rooms,
+    inboxes: Inboxes,
+    room: CompactString,
+    room_tx: broadcast::Sender<RoomMsg>,
+    // subscribed for as long as the user is in the room
+    room_rx: broadcast::Receiver<RoomMsg>,
+    inbox_rx: mpsc::Receiver<InboxMsg>,
+    // taken off a receiver but lost with the connection,
+    // sent again if the session is resumed
+    undelivered: Option<ServerFrame>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let name = &self.user.name;
+        let _ = self.room_tx.send(RoomMsg::Left(name.clone()));
+        self.rooms.leave(&self.room, name);
+        self.inboxes.remove(name);
+    }
+}
+
+fn history(store: &dyn MessageStore, room: &str, count: usize) -> ServerFrame {
+    let messages = store
+        .recent(room, count)
+        .into_iter()
+        .map(|msg| HistoryEntry {
+            spans: markup::parse(&msg.text),
+            from: msg.from.into(),
+            text: msg.text.as_ref().into(),
+            timestamp: msg.timestamp,
+            action: msg.action,
+        })
+        .collect();
+    ServerFrame::History { room: room.into(), messages }
+}
+
+// replayed right after joining a room so newcomers
+// have some context, skipped if nothing was said yet
+fn replay(store: &dyn MessageStore, room: &str, config: &ServerConfig) -> Option<ServerFrame> {
+    if config.history_replay == 0 {
+        return None;
+    }
+    let frame = history(store, room, config.history_replay);
+    match &frame {
+        ServerFrame::History { messages, .. } if messages.is_empty() => None,
+        _ => Some(frame),
+    }
+}
+
+async fn register(accounts: &Accounts, name: &str, password: String) -> ServerFrame {
+    if password.is_empty() {
+        return ServerFrame::error("Usage: /register {password}");
+    }
+    match accounts.register(name, password).await {
+        Ok(()) => ServerFrame::system(format!("Registered {name}, log in with /login {name} {{password}}")),
+        Err(err) => {
+            tracing::warn!("failed to register: {err:?}");
+            ServerFrame::error(err.to_string())
+        }
+    }
+}
+
+// checks the password and claims the account's name,
+// the caller moves the rest of the session over to it
+async fn login(
+    accounts: &Accounts,
+    names: &Names,
+    name: &str,
+    account: String,
+    password: String,
+) -> Result<CompactString, ServerFrame> {
+    if account.is_empty() || password.is_empty() {
+        return Err(ServerFrame::error("Usage: /login {name} {password}"));
+    }
+    if !accounts.verify(&account, password).await {
+        return Err(ServerFrame::error("Wrong name or password"));
+    }
+    let account = CompactString::from(account);
+    if account != name && !names.rename(name, account.clone()) {
+        return Err(ServerFrame::error(format!("{account} is already logged in")));
+    }
+    Ok(account)
+}
+
+// moves everything keyed by the user's name over to the new
+// one, the name itself must already be claimed in `Names`
+fn change_name(
+    rooms: &Rooms,
+    inboxes: &Inboxes,
+    room_tx: &broadcast::Sender<RoomMsg>,
+    room_name: &str,
+    name: &mut CompactString,
+    new_name: CompactString,
+) {
+    rooms.change_name(room_name, name, &new_name);
+    inboxes.rename(name, &new_name);
+    let _ = room_tx.send(RoomMsg::Renamed {
+        from: name.clone(),
+        to: new_name.clone(),
+    });
+    tracing::info!("renamed to {new_name}");
+    tracing::Span::current().record("name", new_name.as_str());
+    *name = new_name;
+}
+
+// sent on switching to json, with the token that
+// resumes this session should the connection drop
+fn welcome(name: &str, room: &str, token: &str) -> ServerFrame {
+    ServerFrame::Welcome {
+        version: PROTOCOL_VERSION,
+        name: name.into(),
+        room: room.into(),
+        resume: Some(token.into()),
+    }
+}
+
+// a resumed session keeps its name and room but the
+// client doesn't know that yet, nor the new token
+async fn resumed_as<O>(
+    sink: &mut FrameSink<O>,
+    name: &str,
+    room: &str,
+    token: &str,
+    undelivered: Option<ServerFrame>,
+) -> Result<(), LinesCodecError>
+where
+    O: Sink<String, Error = LinesCodecError> + Unpin,
+{
+    tracing::info!("resumed as {name} in {room}");
+    tracing::Span::current().record("name", name);
+    tracing::Span::current().record("room", room);
+    sink.send(name, &welcome(name, room, token)).await?;
+    sink.send(name, &ServerFrame::system(format!("Resumed as {name} in {room}"))).await?;
+    match undelivered {
+        Some(frame) => sink.send(name, &frame).await,
+        None => Ok(()),
+    }
+}
+
+// how a user got past the login prompt
+enum Login {
+    Account,
+    Resumed(Box<Session>),
+}
+
+// in require login mode users wait here, outside of any room
+// and without an inbox, until they log in, register or resume
+async fn await_login<I, O>(
+    stream: &mut I,
+    sink: &mut FrameSink<O>,
+    shared: &Shared,
+    limiter: &mut RateLimiter,
+    name: &mut CompactString,
+    token: &str,
+) -> Result<Option<Login>, SessionError>
+where
+    I: Stream<Item = Result<String, LinesCodecError>> + Unpin,
+    O: Sink<String, Error = LinesCodecError> + Unpin,
+{
+    let Shared { accounts, names, resumes, config, .. } = shared;
+    sink.send(name, &ServerFrame::system("Log in with /login {name} {password} or /register {password}")).await?;
+    loop {
+        let user_msg = match stream.next().await {
+            Some(Ok(msg)) => msg,
+            Some(Err(LinesCodecError::MaxLineLengthExceeded)) => {
+                let error = ServerFrame::error(format!("Messages can only be {} chars long", config.max_msg_len));
+                sink.send(name, &error).await?;
+                continue;
+            }
+            Some(Err(_)) | None => return Ok(None),
+        };
+        let frame = match sink.format {
+            Format::Text => ClientFrame::from_line(&user_msg).map_err(|err| err.to_string()),
+            Format::Json => serde_json::from_str(&user_msg).map_err(|err| format!("Invalid frame: {err}")),
+        };
+        if let Some((reply, disconnect)) = rate_limit(limiter, &frame) {
+            sink.send(name, &reply).await?;
+            if disconnect {
+                return Ok(None);
+            }
+            continue;
+        }
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => {
+                sink.send(name, &ServerFrame::Error { text: err }).await?;
+                continue;
+            }
+        };
+        match frame {
+            ClientFrame::Help => sink.send(name, &ServerFrame::Help { text: HELP_MSG.into() }).await?,
+            ClientFrame::Quit => return Ok(None),
+            ClientFrame::Protocol { format, version } => {
+                if version != PROTOCOL_VERSION {
+                    let error = ServerFrame::error(format!("Unsupported protocol version {version}, server speaks {PROTOCOL_VERSION}"));
+                    sink.send(name, &error).await?;
+                    continue;
+                }
+                sink.format = format;
+                let reply = match format {
+                    Format::Json => welcome(name, &config.main_room, token),
+                    Format::Text => ServerFrame::system("Using text protocol"),
+                };
+                sink.send(name, &reply).await?;
+            }
+            ClientFrame::Register { password } => {
+                let reply = register(accounts, name, password).await;
+                let registered = matches!(reply, ServerFrame::System { .. });
+                sink.send(name, &reply).await?;
+                if registered {
+                    return Ok(Some(Login::Account));
+                }
+            }
+            ClientFrame::Login { name: account, password } => {
+                match login(accounts, names, name, account, password).await {
+                    Ok(account) => {
+                        tracing::info!("logged in as {account}");
+                        tracing::Span::current().record("name", account.as_str());
+                        *name = account;
+                        return Ok(Some(Login::Account));
+                    }
+                    Err(error) => sink.send(name, &error).await?,
+                }
+            }
+            // the token stands in for the password
+            // of whoever the session belonged to
+            ClientFrame::Resume { token } => match resumes.take(&token) {
+                Some(session) => return Ok(Some(Login::Resumed(Box::new(session)))),
+                None => sink.send(name, &ServerFrame::error(RESUME_EXPIRED)).await?,
+            },
+            _ => sink.send(name, &ServerFrame::error("Log in first, see /help")).await?,
+        }
+    }
+}
+
+// None if the frame can go through, otherwise what to tell
+// the user and whether to disconnect them. lines that fail
+// to parse count as commands so they can't be used to flood
+fn rate_limit(limiter: &mut RateLimiter, frame: &Result<ClientFrame, String>) -> Option<(ServerFrame, bool)> {
+    let traffic = match frame {
+        Ok(ClientFrame::Quit) => return None,
+        Ok(ClientFrame::Chat { .. } | ClientFrame::Action { .. } | ClientFrame::Direct { .. }) => Traffic::Chat,
+        _ => Traffic::Command,
+    };
+    let reply = match limiter.check(traffic, Instant::now()) {
+        Verdict::Allow => return None,
+        Verdict::Warn => {
+            tracing::warn!("flooding, warned");
+            ServerFrame::error("You are sending too fast, slow down")
+        }
+        Verdict::Mute(mute) => {
+            tracing::warn!("flooding, muted for {}s", mute.as_secs());
+            ServerFrame::error(format!("You are muted for {}s for flooding", mute.as_secs()))
+        }
+        Verdict::Muted(left) => ServerFrame::error(format!("You are muted for another {}s", secs(left))),
+        Verdict::Disconnect => {
+            tracing::warn!("flooding, disconnected");
+            return Some((ServerFrame::error("Disconnected for flooding"), true));
+        }
+    };
+    Some((reply, false))
+}
+
+// whole seconds, rounded up so nobody is told to wait 0s
+fn secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+// an empty away message means there is none
+fn away_frame(user: &str, message: &str) -> ServerFrame {
+    ServerFrame::Away {
+        user: user.into(),
+        message: (!message.is_empty()).then(|| message.into()),
+    }
+}
+
+// names @mentioned in a message, each once, without
+// trailing punctuation so "@bob," still means bob
+fn mentions(text: &str) -> Vec<&str> {
+    let mut names: Vec<_> = text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_'))
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+// shown on joining so newcomers know what the room is for
+fn topic(rooms: &Rooms, room: &str) -> Option<ServerFrame> {
+    let topic = rooms.topic(room)?;
+    Some(ServerFrame::Topic {
+        room: room.into(),
+        topic: Some(topic.as_ref().into()),
+        by: None,
+    })
+}
+
+// longest line a client may send in any format
+pub(crate) fn max_line_len(max_msg_len: usize) -> usize {
+    max_msg_len * 2 + MAX_FRAME_OVERHEAD
+}
+
+// newline delimited lines over a raw byte stream,
+// used for both plain tcp and tls connections
+pub async fn handle_lines<S>(conn: S, shared: Shared, user: User, peer: Peer)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    // sized for json frames, the session checks
+    // text lines against the tighter limit itself
+    let max_len = max_line_len(shared.config.max_msg_len);
+    let (reader, writer) = tokio::io::split(conn);
+    let stream = FramedRead::new(reader, LinesCodec::new_with_max_length(max_len));
+    let sink = FramedWrite::new(writer, LinesCodec::new());
+    handle_user(stream, sink, shared, user, peer).await;
+}
+
+// generic over the transport so every kind of
+// connection runs through the same session
+pub async fn handle_user<I, O>(stream: I, sink: O, shared: Shared, user: User, peer: Peer)
+where
+    I: Stream<Item = Result<String, LinesCodecError>> + Unpin,
+    O: Sink<String, Error = LinesCodecError> + Unpin,
+{
+    let Err(err) = run_user(stream, sink, &shared, user, peer).await else {
+        return;
+    };
+    shared.metrics.error(err.kind());
+    if err.is_disconnect() {
+        tracing::info!("connection lost: {err}");
+    } else {
+        tracing::error!("session failed: {err}");
+    }
+}
+
+// Ok when the user left on their own or was sent away,
+// whatever the session held is released by the guards
+async fn run_user<I, O>(
+    mut stream: I,
+    sink: O,
+    shared: &Shared,
+    mut user: User,
+    peer: Peer,
+) -> Result<(), SessionError>
+where
+    I: Stream<Item = Result<String, LinesCodecError>> + Unpin,
+    O: Sink<String, Error = LinesCodecError> + Unpin,
+{
+    let Shared { rooms, inboxes, resumes, store, limiter_stats, shutdown, config, .. } = shared;
+    let mut limiter = RateLimiter::new(config, limiter_stats.clone());
+    let main_room = config.main_room.as_str();
+    let mut sink = FrameSink {
+        sink,
+        format: Format::Text,
+    };
+    sink.send(&user.name, &ServerFrame::Help { text: HELP_MSG.into() }).await?;
+    // handed out in every welcome, whoever holds it can
+    // pick this session back up if the connection drops
+    let token = Resumes::token();
+    let mut resumed = None;
+    if config.require_login {
+        let login = tokio::select! {
+            login = await_login(&mut stream, &mut sink, shared, &mut limiter, &mut user.name, &token) => login?,
+            _ = shutdown.cancelled() => {
+                sink.send(&user.name, &ServerFrame::system("Server is shutting down, bye!")).await?;
+                None
+            },
+        };
+        match login {
+            Some(Login::Account) => (),
+            Some(Login::Resumed(parked)) => resumed = Some(parked),
+            None => return Ok(()),
+        }
+    }
+    let ip = peer.addr.ip();
+    let fresh = resumed.is_none();
+    let mut session = match resumed {
+        // the login name goes back, the parked one is ours now
+        Some(session) => {
+            drop(user);
+            *session
+        },
+        None => {
+            sink.send(&user.name, &welcome(&user.name, main_room, &token)).await?;
+            let room_tx = match rooms.join(main_room, &user.name, ip, None) {
+                Ok(room_tx) => room_tx,
+                Err(err) => {
+                    tracing::info!("can't join {main_room}: {err}");
+                    sink.send(&user.name, &ServerFrame::error(format!("Can't join {main_room}, {err}"))).await?;
+                    return Ok(());
+                }
+            };
+            let inbox_rx = inboxes.register(&user.name);
+            let room_rx = room_tx.subscribe();
+            Session {
+                user,
+                rooms: rooms.clone(),
+                inboxes: inboxes.clone(),
+                room: main_room.into(),
+                room_tx,
+                room_rx,
+                inbox_rx,
+                undelivered: None,
+            }
+        },
+    };
+    let Session { user: User { name, .. }, room: room_name, room_tx, undelivered, .. } = &mut session;
+    rooms.set_client(room_name, name, &peer.client(sink.format));
+    if fresh {
+        let greeting = topic(rooms, room_name).into_iter().chain(replay(store.as_ref(), room_name, config));
+        for frame in greeting {
+            sink.send(name, &frame).await?;
+        }
+        let _ = room_tx.send(RoomMsg::Joined(name.clone()));
+    } else {
+        resumed_as(&mut sink, name, room_name, &token, undelivered.take()).await?;
+    }
+    let exit = chat(stream, &mut sink, shared, &mut limiter, &mut session, &peer, &token).await;
+    // a json client that drops gets a grace period to come
+    // back with the token before it's gone for everyone
+    let dropped = match &exit {
+        Ok(exit) => *exit == Exit::Closed,
+        Err(err) => err.is_disconnect(),
+    };
+    let grace = config.resume_grace_secs;
+    if dropped && grace > 0 && sink.format == Format::Json && !shutdown.is_cancelled() {
+        tracing::info!("connection dropped, parking the session for {grace}s");
+        resumes.park(token, session, Duration::from_secs(grace));
+    }
+    exit.map(drop)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    // quit, kicked out or sent away
+    Left,
+    // the connection closed without a word
+    Closed,
+}
+
+// the session proper, until the user leaves or the
+// connection goes away
+async fn chat<I, O>(
+    mut stream: I,
+    sink: &mut FrameSink<O>,
+    shared: &Shared,
+    limiter: &mut RateLimiter,
+    session: &mut Session,
+    peer: &Peer,
+    token: &str,
+) -> Result<Exit, SessionError>
+where
+    I: Stream<Item = Result<String, LinesCodecError>> + Unpin,
+    O: Sink<String, Error = LinesCodecError> + Unpin,
+{
+    let Shared { names, rooms, inboxes, resumes, store, accounts, metrics, shutdown, config, .. } = shared;
+    let max_msg_len = config.max_msg_len;
+    let main_room = config.main_room.as_str();
+    let ip = peer.addr.ip();
+    let Session { user: User { name, .. }, room: room_name, room_tx, room_rx, inbox_rx, undelivered, .. } = session;
+    let mut discarding_long_msg = false;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                sink.send(name, &ServerFrame::system("Server is shutting down, bye!")).await?;
+                return Ok(Exit::Left);
+            },
+            user_msg = stream.next() => {
+                let user_msg = match user_msg {
+                    Some(msg) => match msg{
+                        Ok(ok) => ok,
+                        Err(LinesCodecError::MaxLineLengthExceeded) => {
+                            let error = ServerFrame::error(format!("Messages can only be {max_msg_len} chars long"));
+                            sink.send(name, &error).await?;
+                            discarding_long_msg = true;
+                            continue;
+                        },
+                        Err(LinesCodecError::Io(io_err)) => {
+                            match io_err.kind() {
+                                // user typed invalid utf8 like ^C or ^D
+                                // and is probably trying to quit
+                                ErrorKind::InvalidData | ErrorKind::InvalidInput => {
+                                    return Ok(Exit::Left);
+                                },
+                                _ => return Err(io_err.into()),
+                            }
+                        }
+                    },
+                    None => {
+                        if !discarding_long_msg {
+                            return Ok(Exit::Closed);
+                        }
+                        discarding_long_msg = false;
+                        continue;
+                    }
+                };
+                if sink.format == Format::Text && user_msg.len() > max_msg_len {
+                    let error = ServerFrame::error(format!("Messages can only be {max_msg_len} chars long"));
+                    sink.send(name, &error).await?;
+                    continue;
+                }
+                let frame = match sink.format {
+                    Format::Text => ClientFrame::from_line(&user_msg)
+                        .map_err(|err| err.to_string()),
+                    Format::Json => serde_json::from_str(&user_msg)
+                        .map_err(|err| format!("Invalid frame: {err}")),
+                };
+                if let Some((reply, disconnect)) = rate_limit(limiter, &frame) {
+                    sink.send(name, &reply).await?;
+                    if disconnect {
+                        return Ok(Exit::Left);
+                    }
+                    continue;
+                }
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        sink.send(name, &ServerFrame::Error { text: err }).await?;
+                        continue;
+                    }
+                };
+                // setting away doesn't end it, and neither does listing
+                // rooms or users, which clients also do on their own
+                let active = !matches!(frame, ClientFrame::Away { .. } | ClientFrame::Rooms | ClientFrame::Users);
+                if active && rooms.touch(room_name, name, Instant::now()) {
+                    sink.send(name, &ServerFrame::system("You are no longer away")).await?;
+                }
+                let action = matches!(frame, ClientFrame::Action { .. });
+                if !matches!(frame, ClientFrame::Chat { .. }) && !action {
+                    metrics.command(frame.name());
+                }
+                match frame {
+                    ClientFrame::Help => {
+                        sink.send(name, &ServerFrame::Help { text: HELP_MSG.into() }).await?;
+                    },
+                    ClientFrame::Rename { name: new_name } => {
+                        if !valid_name(Some(&new_name)){
+                            sink.send(name, &ServerFrame::error("Name must be 2 - 20 alphanumeric chars")).await?;
+                            continue;
+                        }
+                        let new_name = CompactString::from(new_name);
+                        if new_name == name {
+                            sink.send(name, &ServerFrame::system(format!("You are {name}"))).await?;
+                            continue;
+                        }
+                        if accounts.is_registered(&new_name) {
+                            let error = ServerFrame::error(format!("{new_name} is registered, use /login {new_name} {{password}}"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        if !names.rename(name, new_name.clone()) {
+                            sink.send(name, &ServerFrame::error(format!("{new_name} is already taken"))).await?;
+                            continue;
+                        }
+                        change_name(rooms, inboxes, room_tx, room_name, name, new_name);
+                    },
+                    ClientFrame::Register { password } => {
+                        sink.send(name, &register(accounts, name, password).await).await?;
+                    },
+                    ClientFrame::Login { name: account, password } => {
+                        match login(accounts, names, name, account, password).await {
+                            Ok(account) if account == name => {
+                                sink.send(name, &ServerFrame::system(format!("Logged in as {name}"))).await?;
+                            },
+                            Ok(account) => {
+                                tracing::info!("logged in as {account}");
+                                change_name(rooms, inboxes, room_tx, room_name, name, account);
+                            },
+                            Err(error) => sink.send(name, &error).await?,
+                        }
+                    },
+                    ClientFrame::Join { room: new_room, key } => {
+                        if !valid_name(Some(&new_room)) {
+                            sink.send(name, &ServerFrame::error("Room must be 2 - 20 alphanumeric chars")).await?;
+                            continue;
+                        }
+                        let new_room = CompactString::from(new_room);
+                        if new_room == room_name {
+                            sink.send(name, &ServerFrame::system(format!("You are in {room_name}"))).await?;
+                            continue;
+                        }
+                        let new_tx = match rooms.change(room_name, &new_room, name, ip, key.as_deref()) {
+                            Ok(new_tx) => new_tx,
+                            Err(err) => {
+                                sink.send(name, &ServerFrame::error(format!("Can't join {new_room}, {err}"))).await?;
+                                continue;
+                            }
+                        };
+                        let _ = room_tx.send(RoomMsg::Left(name.clone()));
+                        *room_tx = new_tx;
+                        *room_rx = room_tx.subscribe();
+                        tracing::info!("joined {new_room}");
+                        tracing::Span::current().record("room", new_room.as_str());
+                        *room_name = new_room;
+                        if let Some(topic) = topic(rooms, room_name) {
+                            sink.send(name, &topic).await?;
+                        }
+                        if let Some(replay) = replay(store.as_ref(), room_name, config) {
+                            sink.send(name, &replay).await?;
+                        }
+                        let _ = room_tx.send(RoomMsg::Joined(name.clone()));
+                    },
+                    ClientFrame::Rooms => {
+                        sink.send(name, &ServerFrame::Rooms { rooms: rooms.list(name) }).await?;
+                    },
+                    ClientFrame::Users => {
+                        let users = ServerFrame::Users {
+                            room: room_name.to_string(),
+                            users: rooms.list_users(room_name, Instant::now()),
+                        };
+                        sink.send(name, &users).await?;
+                    },
+                    ClientFrame::SetRole { user, role } => {
+                        if user.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /op, /deop, /voice or /devoice {user}")).await?;
+                            continue;
+                        }
+                        match rooms.set_role(room_name, name, &user, role) {
+                            Ok(()) => {
+                                tracing::info!("made {user} {}", role.as_str());
+                                let _ = room_tx.send(RoomMsg::RoleChanged {
+                                    user: user.into(),
+                                    role,
+                                    by: name.clone(),
+                                });
+                            },
+                            Err(err) => sink.send(name, &ServerFrame::error(err.to_string())).await?,
+                        }
+                    },
+                    ClientFrame::Kick { user, reason } => {
+                        if user.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /kick {user} [reason]")).await?;
+                            continue;
+                        }
+                        // a kick from the main room disconnects
+                        if room_name == main_room {
+                            sink.send(name, &ServerFrame::error(format!("Nobody can be kicked from {main_room}"))).await?;
+                            continue;
+                        }
+                        match rooms.can_kick(room_name, name, &user) {
+                            Ok(()) => {
+                                tracing::info!("kicked {user}");
+                                let _ = room_tx.send(RoomMsg::Kicked {
+                                    user: user.into(),
+                                    by: name.clone(),
+                                    reason: reason.map(Arc::from),
+                                });
+                            },
+                            Err(err) => sink.send(name, &ServerFrame::error(err.to_string())).await?,
+                        }
+                    },
+                    ClientFrame::Ban { target } => {
+                        if target.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /ban {user|ip}")).await?;
+                            continue;
+                        }
+                        // a ban from the main room would keep
+                        // the user off the server altogether
+                        if room_name == main_room {
+                            sink.send(name, &ServerFrame::error(format!("Nobody can be banned from {main_room}"))).await?;
+                            continue;
+                        }
+                        match rooms.ban(room_name, name, &target) {
+                            Ok(banned) => {
+                                tracing::info!("banned {target}");
+                                let reason: Arc<str> = Arc::from("banned");
+                                for user in banned {
+                                    let _ = room_tx.send(RoomMsg::Kicked {
+                                        user,
+                                        by: name.clone(),
+                                        reason: Some(reason.clone()),
+                                    });
+                                }
+                                sink.send(name, &ServerFrame::system(format!("Banned {target} from {room_name}"))).await?;
+                            },
+                            Err(err) => sink.send(name, &ServerFrame::error(err.to_string())).await?,
+                        }
+                    },
+                    ClientFrame::Unban { target } => {
+                        if target.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /unban {user|ip}")).await?;
+                            continue;
+                        }
+                        let reply = match rooms.unban(room_name, name, &target) {
+                            Ok(()) => {
+                                tracing::info!("unbanned {target}");
+                                ServerFrame::system(format!("Unbanned {target} from {room_name}"))
+                            },
+                            Err(err) => ServerFrame::error(err.to_string()),
+                        };
+                        sink.send(name, &reply).await?;
+                    },
+                    ClientFrame::Topic { topic: None } => {
+                        let topic = ServerFrame::Topic {
+                            room: room_name.to_string(),
+                            topic: rooms.topic(room_name).as_deref().map(String::from),
+                            by: None,
+                        };
+                        sink.send(name, &topic).await?;
+                    },
+                    ClientFrame::Topic { topic: Some(topic) } => {
+                        if topic.len() > max_msg_len {
+                            let error = ServerFrame::error(format!("Topics can only be {max_msg_len} chars long"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        let topic: Arc<str> = Arc::from(topic);
+                        match rooms.set_topic(room_name, name, topic.clone()) {
+                            Ok(()) => {
+                                tracing::info!("set topic of {room_name}");
+                                let _ = room_tx.send(RoomMsg::TopicChanged { by: name.clone(), topic });
+                            },
+                            Err(err) => sink.send(name, &ServerFrame::error(err.to_string())).await?,
+                        }
+                    },
+                    ClientFrame::Mode { change: None } => {
+                        let modes = ServerFrame::Modes {
+                            room: room_name.to_string(),
+                            modes: rooms.modes(room_name),
+                            by: None,
+                        };
+                        sink.send(name, &modes).await?;
+                    },
+                    ClientFrame::Mode { change: Some(change) } => {
+                        // everyone lands in the main room and
+                        // nobody runs it, so its modes stay put
+                        if room_name == main_room {
+                            sink.send(name, &ServerFrame::error(format!("{main_room} modes can't be changed"))).await?;
+                            continue;
+                        }
+                        match rooms.set_mode(room_name, name, change) {
+                            Ok(modes) => {
+                                tracing::info!("set modes of {room_name} to {}", modes.flags());
+                                let _ = room_tx.send(RoomMsg::ModesChanged { by: name.clone(), modes });
+                            },
+                            Err(err) => sink.send(name, &ServerFrame::error(err.to_string())).await?,
+                        }
+                    },
+                    ClientFrame::Invite { user } => {
+                        if user.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /invite {user}")).await?;
+                            continue;
+                        }
+                        let reply = match rooms.invite(room_name, name, &user) {
+                            Ok(()) => {
+                                tracing::info!("invited {user}");
+                                // the invite stands even if they're
+                                // offline and never get the notice
+                                let _ = inboxes.invite(&user, room_name, name);
+                                ServerFrame::system(format!("Invited {user} to {room_name}"))
+                            },
+                            Err(err) => ServerFrame::error(err.to_string()),
+                        };
+                        sink.send(name, &reply).await?;
+                    },
+                    ClientFrame::Uninvite { user } => {
+                        if user.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /uninvite {user}")).await?;
+                            continue;
+                        }
+                        let reply = match rooms.uninvite(room_name, name, &user) {
+                            Ok(()) => ServerFrame::system(format!("Uninvited {user} from {room_name}")),
+                            Err(err) => ServerFrame::error(err.to_string()),
+                        };
+                        sink.send(name, &reply).await?;
+                    },
+                    ClientFrame::Away { message } => {
+                        let message = message.unwrap_or_default();
+                        if message.len() > max_msg_len {
+                            let error = ServerFrame::error(format!("Away messages can only be {max_msg_len} chars long"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        rooms.set_away(room_name, name, Arc::from(message.as_str()), Instant::now());
+                        tracing::info!("went away");
+                        sink.send(name, &away_frame(name, &message)).await?;
+                    },
+                    ClientFrame::Whois { user } => {
+                        if user.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /whois {user}")).await?;
+                            continue;
+                        }
+                        let reply = match rooms.whois(&user, name, Instant::now()) {
+                            Some(whois) => ServerFrame::Whois { user: whois },
+                            None => ServerFrame::error(format!("{user} is not online")),
+                        };
+                        sink.send(name, &reply).await?;
+                    },
+                    ClientFrame::History { count } => {
+                        let count = count.unwrap_or(config.history_replay).min(config.history_size);
+                        sink.send(name, &history(store.as_ref(), room_name, count)).await?;
+                    },
+                    ClientFrame::Quit => {
+                        return Ok(Exit::Left);
+                    },
+                    ClientFrame::Protocol { format, version } => {
+                        if version != PROTOCOL_VERSION {
+                            let error = ServerFrame::error(format!("Unsupported protocol version {version}, server speaks {PROTOCOL_VERSION}"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        sink.format = format;
+                        rooms.set_client(room_name, name, &peer.client(format));
+                        let reply = match format {
+                            Format::Json => welcome(name, room_name, token),
+                            Format::Text => ServerFrame::system("Using text protocol"),
+                        };
+                        sink.send(name, &reply).await?;
+                    },
+                    ClientFrame::Resume { token: resume } => {
+                        let Some(mut parked) = resumes.take(&resume) else {
+                            sink.send(name, &ServerFrame::error(RESUME_EXPIRED)).await?;
+                            continue;
+                        };
+                        // carry on as the parked session, the one we
+                        // were is dropped and leaves like any other
+                        std::mem::swap(name, &mut parked.user.name);
+                        std::mem::swap(room_name, &mut parked.room);
+                        std::mem::swap(room_tx, &mut parked.room_tx);
+                        std::mem::swap(room_rx, &mut parked.room_rx);
+                        std::mem::swap(inbox_rx, &mut parked.inbox_rx);
+                        let missed = parked.undelivered.take();
+                        drop(parked);
+                        rooms.set_client(room_name, name, &peer.client(sink.format));
+                        resumed_as(sink, name, room_name, token, missed).await?;
+                    },
+                    ClientFrame::Direct { to, text } => {
+                        if to.is_empty() || text.is_empty() {
+                            sink.send(name, &ServerFrame::error("Usage: /msg {user} {text}")).await?;
+                            continue;
+                        }
+                        if text.len() > max_msg_len {
+                            let error = ServerFrame::error(format!("Messages can only be {max_msg_len} chars long"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        let to = CompactString::from(to);
+                        let text: Arc<str> = Arc::from(text);
+                        let msg = DirectMsg {
+                            from: name.clone(),
+                            to: to.clone(),
+                            text: text.clone(),
+                        };
+                        let reply = match inboxes.send(msg) {
+                            // echo it back so the sender sees what they sent
+                            Ok(()) => {
+                                metrics.direct_messages.fetch_add(1, Ordering::Relaxed);
+                                ServerFrame::Direct {
+                                    from: name.to_string(),
+                                    to: to.to_string(),
+                                    text: text.as_ref().into(),
+                                }
+                            },
+                            Err(DeliveryError::Offline) => ServerFrame::error(format!("{to} is not online")),
+                            Err(DeliveryError::Full) => {
+                                ServerFrame::error(format!("{to} can't receive messages right now, try again later"))
+                            },
+                        };
+                        let delivered = matches!(reply, ServerFrame::Direct { .. });
+                        sink.send(name, &reply).await?;
+                        let away = rooms.whois(&to, name, Instant::now()).and_then(|whois| whois.away);
+                        if delivered && let Some(away) = away {
+                            sink.send(name, &away_frame(&to, &away)).await?;
+                        }
+                    },
+                    ClientFrame::Action { text } if text.is_empty() => {
+                        sink.send(name, &ServerFrame::error("Usage: /me {action}")).await?;
+                    },
+                    ClientFrame::Chat { text } | ClientFrame::Action { text } => {
+                        // json lines are allowed to be longer
+                        // to fit the frame so check the text too
+                        if text.len() > max_msg_len {
+                            let error = ServerFrame::error(format!("Messages can only be {max_msg_len} chars long"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        if !rooms.can_speak(room_name, name) {
+                            let error = ServerFrame::error(format!("{room_name} is moderated, only voiced users can talk"));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        if let Err(wait) = rooms.check_slow_mode(room_name, name, Instant::now()) {
+                            let error = ServerFrame::error(format!("{room_name} is in slow mode, wait {}s", secs(wait)));
+                            sink.send(name, &error).await?;
+                            continue;
+                        }
+                        let text: Arc<str> = Arc::from(text);
+                        store.append(StoredMsg { action, ..StoredMsg::now(room_name, name, text.clone()) });
+                        metrics.messages.fetch_add(1, Ordering::Relaxed);
+                        // users here get the message flagged, anyone
+                        // online elsewhere gets a notification instead
+                        let (here, elsewhere): (Vec<_>, Vec<_>) = mentions(&text)
+                            .into_iter()
+                            .filter(|user| user != name)
+                            .partition(|user| rooms.role(room_name, user).is_some());
+                        let _ = room_tx.send(RoomMsg::Msg {
+                            from: name.clone(),
+                            spans: markup::parse(&text).into(),
+                            text: text.clone(),
+                            action,
+                            mentioned: here.iter().map(|&user| user.into()).collect(),
+                        });
+                        // let them know who won't be answering
+                        for user in here {
+                            if let Some(away) = rooms.away(room_name, user) {
+                                sink.send(name, &away_frame(user, &away)).await?;
+                            }
+                        }
+                        for user in elsewhere {
+                            // an offline or busy user misses out, it's
+                            // just a notification and the message is sent
+                            if inboxes.mention(user, room_name, name, text.clone()).is_err() {
+                                continue;
+                            }
+                            let away = rooms.whois(user, name, Instant::now()).and_then(|whois| whois.away);
+                            if let Some(away) = away {
+                                sink.send(name, &away_frame(user, &away)).await?;
+                            }
+                        }
+                    },
+                }
+            },
+            inbox_msg = inbox_rx.recv() => {
+                // we hold our own inbox registration until
+                // we exit, so the channel can't be closed
+                let Some(inbox_msg) = inbox_msg else {
+                    continue;
+                };
+                let frame = match inbox_msg {
+                    InboxMsg::Direct(DirectMsg { from, to, text }) => ServerFrame::Direct {
+                        from: from.into(),
+                        to: to.into(),
+                        text: text.as_ref().into(),
+                    },
+                    InboxMsg::Invite { room, by } => ServerFrame::Invited {
+                        room: room.into(),
+                        by: by.into(),
+                    },
+                    InboxMsg::Mention { room, from, text } => ServerFrame::Mentioned {
+                        room: room.into(),
+                        from: from.into(),
+                        spans: markup::parse(&text),
+                        text: text.as_ref().into(),
+                    },
+                };
+                if let Err(err) = sink.send(name, &frame).await {
+                    *undelivered = Some(frame);
+                    return Err(err.into());
+                }
+            },
+            peer_msg = room_rx.recv() => {
+                let peer_msg = match peer_msg {
+                    Ok(ok) => ok,
+                    // we would get this error if all tx
+                    // were dropped for this rx, which is not
+                    // possible since we're holding a tx,
+                    // but if this were to somehow ever happen
+                    // we just put the user back into the main
+                    // room
+                    Err(RecvError::Closed) => {
+                        let _ = room_tx.send(RoomMsg::Left(name.clone()));
+                        let Ok(main_tx) = rooms.change(room_name, main_room, name, ip, None) else {
+                            return Ok(Exit::Left);
+                        };
+                        *room_tx = main_tx;
+                        *room_rx = room_tx.subscribe();
+                        *room_name = main_room.into();
+                        tracing::Span::current().record("room", main_room);
+                        let _ = room_tx.send(RoomMsg::Joined(name.clone()));
+                        continue;
+                    },
+                    // under high load we might not deliver all msgs
+                    // to all users in a room, in which case we let
+                    // them know that we dropped some msgs
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("Server dropped {n} messages for {room_name} with {} users", room_tx.receiver_count());
+                        metrics.lagged.fetch_add(n, Ordering::Relaxed);
+                        let notice = ServerFrame::system(format!("Server is very busy and dropped {n} messages, sorry!"));
+                        sink.send(name, &notice).await?;
+                        continue;
+                    }
+                };
+                // kicked users go back to the main room, or
+                // get disconnected if that's where they were
+                if let RoomMsg::Kicked { user, by, reason } = &peer_msg && *user == name {
+                    let kicked = ServerFrame::Kicked {
+                        room: room_name.to_string(),
+                        user: name.to_string(),
+                        by: by.to_string(),
+                        reason: reason.as_deref().map(String::from),
+                    };
+                    sink.send(name, &kicked).await?;
+                    tracing::info!("kicked from {room_name} by {by}");
+                    if room_name == main_room {
+                        return Ok(Exit::Left);
+                    }
+                    let Ok(main_tx) = rooms.change(room_name, main_room, name, ip, None) else {
+                        return Ok(Exit::Left);
+                    };
+                    *room_tx = main_tx;
+                    *room_rx = room_tx.subscribe();
+                    *room_name = main_room.into();
+                    tracing::Span::current().record("room", main_room);
+                    if let Some(topic) = topic(rooms, room_name) {
+                        sink.send(name, &topic).await?;
+                    }
+                    if let Some(replay) = replay(store.as_ref(), room_name, config) {
+                        sink.send(name, &replay).await?;
+                    }
+                    let _ = room_tx.send(RoomMsg::Joined(name.clone()));
+                    continue;
+                }
+                let frame = match peer_msg {
+                    RoomMsg::Joined(peer_name) => ServerFrame::Joined {
+                        room: room_name.to_string(),
+                        user: peer_name.into(),
+                    },
+                    RoomMsg::Left(peer_name) => ServerFrame::Left {
+                        room: room_name.to_string(),
+                        user: peer_name.into(),
+                    },
+                    RoomMsg::Renamed { from, to } => ServerFrame::Renamed {
+                        from: from.into(),
+                        to: to.into(),
+                    },
+                    RoomMsg::Msg { from, text, spans, action, mentioned } => {
+                        let mention = mentioned.iter().any(|user| user == name);
+                        let (from, text, spans) = (from.into(), text.as_ref().into(), spans.to_vec());
+                        if action {
+                            ServerFrame::Action { from, text, spans, mention }
+                        } else {
+                            ServerFrame::Chat { from, text, spans, mention }
+                        }
+                    },
+                    RoomMsg::RoleChanged { user, role, by } => ServerFrame::RoleChanged {
+                        room: room_name.to_string(),
+                        user: user.into(),
+                        role,
+                        by: by.into(),
+                    },
+                    RoomMsg::Kicked { user, by, reason } => ServerFrame::Kicked {
+                        room: room_name.to_string(),
+                        user: user.into(),
+                        by: by.into(),
+                        reason: reason.as_deref().map(String::from),
+                    },
+                    RoomMsg::TopicChanged { by, topic } => ServerFrame::Topic {
+                        room: room_name.to_string(),
+                        topic: Some(topic.as_ref().into()),
+                        by: Some(by.into()),
+                    },
+                    RoomMsg::ModesChanged { by, modes } => ServerFrame::Modes {
+                        room: room_name.to_string(),
+                        modes,
+                        by: Some(by.into()),
+                    },
+                    RoomMsg::ShuttingDown(grace) => {
+                        ServerFrame::system(format!("Server is shutting down in {}s", secs(grace)))
+                    },
+                };
+                if let Err(err) = sink.send(name, &frame).await {
+                    *undelivered = Some(frame);
+                    return Err(err.into());
+                }
+            },
+        }
+    }
+}
+
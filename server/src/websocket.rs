@@ -0,0 +1,135 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures::{future, SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_util::codec::LinesCodecError;
+use crate::{
+    handle_user,
+    http::{read_request_head, request_line, write_response, METHOD_NOT_ALLOWED, NOT_FOUND, OK, REQUEST_TIMEOUT},
+    session::max_line_len,
+    Peer, Shared, User,
+};
+
+pub const INDEX_HTML: &str = include_str!("index.html");
+
+// browsers get the bundled page over plain http and then
+// open a websocket to the same port, every text frame is
+// handled exactly like a line from a tcp client
+pub async fn handle_websocket<S>(mut conn: S, shared: Shared, user: User, peer: Peer)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut conn)).await {
+        Ok(Ok(head)) => head,
+        Ok(Err(err)) => {
+            tracing::warn!("bad http request: {err}");
+            return;
+        }
+        Err(_) => {
+            tracing::warn!("http request timed out");
+            return;
+        }
+    };
+    if !is_upgrade(&head) {
+        // page loads don't keep the name
+        drop(user);
+        if let Err(err) = serve_page(&mut conn, &head).await {
+            tracing::warn!("failed to serve page: {err}");
+        }
+        return;
+    }
+    // hand the bytes we already read back to the handshake
+    let conn = Rewind { prefix: head, pos: 0, inner: conn };
+    let ws = match tokio_tungstenite::accept_async(conn).await {
+        Ok(ws) => ws,
+        Err(err) => {
+            tracing::warn!("websocket handshake failed: {err}");
+            return;
+        }
+    };
+    let max_len = max_line_len(shared.config.max_msg_len);
+    let (ws_sink, ws_stream) = ws.split();
+    let stream = ws_stream.filter_map(move |msg| {
+        future::ready(match msg {
+            Ok(Message::Text(text)) if text.len() > max_len => {
+                Some(Err(LinesCodecError::MaxLineLengthExceeded))
+            }
+            Ok(Message::Text(text)) => Some(Ok(text.as_str().to_owned())),
+            // pings are answered by tungstenite itself
+            // and the stream ends right after a close
+            Ok(_) => None,
+            Err(err) => Some(Err(ws_error(err))),
+        })
+    });
+    let sink = ws_sink
+        .sink_map_err(ws_error)
+        .with(|line: String| future::ready(Ok::<_, LinesCodecError>(Message::text(line))));
+    handle_user(Box::pin(stream), Box::pin(sink), shared, user, peer).await;
+}
+
+// map onto the io errors the session already
+// knows how to treat as a disconnect
+fn ws_error(err: tungstenite::Error) -> LinesCodecError {
+    let err = match err {
+        tungstenite::Error::Io(err) => err,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::Error::from(io::ErrorKind::ConnectionReset)
+        }
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    };
+    LinesCodecError::Io(err)
+}
+
+fn is_upgrade(head: &[u8]) -> bool {
+    String::from_utf8_lossy(head).lines().any(|line| {
+        let Some((key, value)) = line.split_once(':') else {
+            return false;
+        };
+        key.trim().eq_ignore_ascii_case("upgrade") && value.trim().eq_ignore_ascii_case("websocket")
+    })
+}
+
+async fn serve_page<S: AsyncWrite + Unpin>(conn: &mut S, head: &[u8]) -> Result<(), io::Error> {
+    let (status, content_type, body) = match request_line(head) {
+        (Some("GET"), Some("/" | "/index.html")) => (OK, "text/html; charset=utf-8", INDEX_HTML),
+        (Some("GET"), _) => (NOT_FOUND, "text/plain", "Not found\n"),
+        _ => (METHOD_NOT_ALLOWED, "text/plain", "Method not allowed\n"),
+    };
+    write_response(conn, status, content_type, body).await
+}
+
+// replays bytes that were already read off the
+// connection before reading from it again
+struct Rewind<S> {
+    prefix: Vec<u8>,
+    pos: usize,
+    inner: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.pos < self.prefix.len() {
+            let n = buf.remaining().min(self.prefix.len() - self.pos);
+            buf.put_slice(&self.prefix[self.pos..self.pos + n]);
+            self.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use chat_server::{AccountError, Accounts, ServerConfig};
+use common::{spawn_server, spawn_server_with, Client};
+
+#[tokio::test]
+async fn registered_names_need_a_password() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/name alice").await;
+    alice.recv_until(|line| line == "You are now alice").await;
+    alice.send("/register secret").await;
+    alice.recv_until(|line| line.starts_with("Registered alice")).await;
+    alice.send("/quit").await;
+
+    let mut guest = Client::connect(addr).await;
+    guest.send("/name alice").await;
+    guest.recv_until(|line| line == "alice is registered, use /login alice {password}").await;
+    guest.send("/login alice wrong").await;
+    guest.recv_until(|line| line == "Wrong name or password").await;
+    guest.send("/login alice secret").await;
+    guest.recv_until(|line| line == "You are now alice").await;
+}
+
+#[tokio::test]
+async fn accounts_can_only_be_logged_in_once() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/name alice2").await;
+    alice.recv_until(|line| line == "You are now alice2").await;
+    alice.send("/register short").await;
+    alice.recv_until(|line| line == "Password must be at least 6 chars").await;
+    alice.send("/register secret").await;
+    alice.recv_until(|line| line.starts_with("Registered alice2")).await;
+
+    let mut other = Client::connect(addr).await;
+    other.send("/login alice2 secret").await;
+    other.recv_until(|line| line == "alice2 is already logged in").await;
+}
+
+#[tokio::test]
+async fn require_login_holds_users_back() {
+    let addr = spawn_server_with(ServerConfig {
+        require_login: true,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect_raw(addr).await;
+    alice.recv_until(|line| line.starts_with("Log in with")).await;
+    alice.send("/rooms").await;
+    alice.recv_until(|line| line == "Log in first, see /help").await;
+    alice.send("hello").await;
+    alice.recv_until(|line| line == "Log in first, see /help").await;
+    alice.send("/register secret").await;
+    let registered = alice.recv_until(|line| line.starts_with("Registered ")).await;
+    let name = registered["Registered ".len()..].split(',').next().unwrap().to_owned();
+    alice.recv_until(|line| line == format!("You are {name}")).await;
+    alice.recv_until(|line| line == "You joined main").await;
+    alice.send("/quit").await;
+
+    let mut again = Client::connect_raw(addr).await;
+    again.recv_until(|line| line.starts_with("Log in with")).await;
+    again.send(&format!("/login {name} secret")).await;
+    again.recv_until(|line| line == format!("You are {name}")).await;
+    again.recv_until(|line| line == "You joined main").await;
+}
+
+#[tokio::test]
+async fn accounts_persist_to_file() {
+    let path = std::env::temp_dir().join(format!("{}-accounts.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let accounts = Accounts::open(&path).unwrap();
+        accounts.register("alice", "secret".into()).await.unwrap();
+        assert!(matches!(
+            accounts.register("alice", "secret".into()).await,
+            Err(AccountError::AlreadyRegistered),
+        ));
+    }
+    let accounts = Accounts::open(&path).unwrap();
+    assert!(accounts.is_registered("alice"));
+    assert!(accounts.verify("alice", "secret".into()).await);
+    assert!(!accounts.verify("alice", "wrong".into()).await);
+    assert!(!accounts.verify("bob", "secret".into()).await);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn bad_account_lines_are_skipped() {
+    let path = std::env::temp_dir().join(format!("{}-torn-accounts.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let accounts = Accounts::open(&path).unwrap();
+        accounts.register("alice", "secret".into()).await.unwrap();
+    }
+    let mut log = std::fs::read_to_string(&path).unwrap();
+    log.insert_str(0, "{\"name\":\"bob\",\"ha\n");
+    std::fs::write(&path, log).unwrap();
+
+    let accounts = Accounts::open(&path).unwrap();
+    assert!(accounts.verify("alice", "secret".into()).await);
+    assert!(!accounts.is_registered("bob"));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use chat_protocol::{ClientFrame, ServerFrame};
+use common::{spawn_server, Client};
+
+#[tokio::test]
+async fn away_users_auto_reply() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    bob.send("/away lunch").await;
+    bob.recv_until(|line| line == "You are away: lunch").await;
+    // just looking around doesn't count as being back
+    bob.send("/rooms").await;
+    bob.send("/users").await;
+    bob.recv_until(|line| line.starts_with("Users - ") && line.contains("(away: lunch)")).await;
+
+    let away = format!("{} is away: lunch", bob.name);
+    alice.send(&format!("/msg {} you there?", bob.name)).await;
+    alice.recv_until(|line| line == away).await;
+    alice.send(&format!("hey @{}, ping", bob.name)).await;
+    alice.recv_until(|line| line == away).await;
+
+    alice.send("/users").await;
+    alice.recv_until(|line| line.starts_with("Users - ") && line.contains("(away: lunch)")).await;
+
+    bob.send("back").await;
+    bob.recv_until(|line| line == "You are no longer away").await;
+    alice.send(&format!("/msg {} welcome back", bob.name)).await;
+    alice.send("/topic").await;
+    loop {
+        let line = alice.recv().await;
+        assert_ne!(line, away);
+        if line.starts_with("No topic in ") {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn whois_reports_room_and_client() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    alice.send(&format!("/whois {}", bob.name)).await;
+    let whois = format!("{} is in main via tcp/text, online 0s, idle 0s", bob.name);
+    alice.recv_until(|line| line == whois).await;
+    alice.send("/whois nobody").await;
+    alice.recv_until(|line| line == "nobody is not online").await;
+    alice.send("/whois").await;
+    alice.recv_until(|line| line == "Usage: /whois {user}").await;
+
+    bob.send("/away").await;
+    bob.recv_until(|line| line == "You are away").await;
+    alice.use_json().await;
+    alice.send_frame(&ClientFrame::Whois { user: bob.name.clone() }).await;
+    let frame = alice.recv_frame_until(|frame| matches!(frame, ServerFrame::Whois { .. })).await;
+    let ServerFrame::Whois { user } = frame else { unreachable!() };
+    assert_eq!(user.room.as_deref(), Some("main"));
+    assert_eq!(user.away.as_deref(), Some(""));
+    alice.send_frame(&ClientFrame::Whois { user: alice.name.clone() }).await;
+    let frame = alice.recv_frame_until(|frame| matches!(frame, ServerFrame::Whois { .. })).await;
+    let ServerFrame::Whois { user } = frame else { unreachable!() };
+    assert_eq!(user.client, "tcp/json");
+}
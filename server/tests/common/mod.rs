@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use chat_protocol::{ClientFrame, ServerFrame};
+use chat_server::ServerConfig;
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream, tcp::{OwnedReadHalf, OwnedWriteHalf}};
+use tokio::time::timeout;
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+pub async fn spawn_server() -> SocketAddr {
+    spawn_server_with(ServerConfig::default()).await
+}
+
+pub async fn spawn_server_with(config: ServerConfig) -> SocketAddr {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(chat_server::serve(vec![server], Vec::new(), Vec::new(), config));
+    addr
+}
+
+pub struct Client {
+    pub name: String,
+    stream: FramedRead<OwnedReadHalf, LinesCodec>,
+    sink: FramedWrite<OwnedWriteHalf, LinesCodec>,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let mut client = Self::connect_raw(addr).await;
+        let greeting = client.recv_until(|line| line.starts_with("You are ")).await;
+        client.name = greeting["You are ".len()..].to_owned();
+        client.recv_until(|line| line.starts_with("You joined ")).await;
+        client
+    }
+
+    // connects without waiting for the greeting
+    pub async fn connect_raw(addr: SocketAddr) -> Self {
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (reader, writer) = tcp.into_split();
+        Self {
+            name: String::new(),
+            stream: FramedRead::new(reader, LinesCodec::new()),
+            sink: FramedWrite::new(writer, LinesCodec::new()),
+        }
+    }
+
+    // hangs up with a reset instead of a clean close,
+    // like a crashed client or a dropped network
+    pub fn abort(self) {
+        let tcp = self.stream.into_inner().reunite(self.sink.into_inner()).unwrap();
+        tcp.set_zero_linger().unwrap();
+    }
+
+    pub async fn send(&mut self, line: &str) {
+        self.sink.send(line).await.unwrap();
+    }
+
+    pub async fn recv(&mut self) -> String {
+        timeout(WAIT, self.stream.next())
+            .await
+            .expect("timed out waiting for a line")
+            .expect("server closed the connection")
+            .unwrap()
+    }
+
+    // reads until the server hangs up, panics if it doesn't
+    pub async fn recv_closed(&mut self) {
+        loop {
+            match timeout(WAIT, self.stream.next()).await.expect("timed out waiting for close") {
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            }
+        }
+    }
+
+    // skips over lines until one matches, so tests
+    // don't have to care about unrelated chatter
+    pub async fn recv_until(&mut self, pred: impl Fn(&str) -> bool) -> String {
+        loop {
+            let line = self.recv().await;
+            if pred(&line) {
+                return line;
+            }
+        }
+    }
+
+    pub async fn send_frame(&mut self, frame: &ClientFrame) {
+        self.send(&serde_json::to_string(frame).unwrap()).await;
+    }
+
+    // switches to the json protocol, skipping any
+    // plain text lines sent before the switch
+    pub async fn use_json(&mut self) {
+        self.send("/protocol json").await;
+        let welcome = self.recv_until(|line| line.starts_with('{')).await;
+        let welcome: ServerFrame = serde_json::from_str(&welcome).unwrap();
+        assert!(matches!(welcome, ServerFrame::Welcome { .. }));
+    }
+
+    pub async fn recv_frame(&mut self) -> ServerFrame {
+        serde_json::from_str(&self.recv().await).unwrap()
+    }
+
+    pub async fn recv_frame_until(&mut self, pred: impl Fn(&ServerFrame) -> bool) -> ServerFrame {
+        loop {
+            let frame = self.recv_frame().await;
+            if pred(&frame) {
+                return frame;
+            }
+        }
+    }
+}
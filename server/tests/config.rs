@@ -0,0 +1,128 @@
+mod common;
+
+use std::path::PathBuf;
+use clap::Parser;
+use chat_server::{Cli, LogFormat, LogRotation, ServerConfig};
+use common::{spawn_server_with, Client};
+
+fn write_config(file_name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{file_name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn defaults_without_flags() {
+    let cli = Cli::try_parse_from(["chat-server"]).unwrap();
+    assert_eq!(ServerConfig::from_cli(cli).unwrap(), ServerConfig::default());
+}
+
+#[test]
+fn flags_override_config_file() {
+    let path = write_config(
+        "override.toml",
+        "bind = [\"127.0.0.1:9000\"]\nmax_msg_len = 100\nmain_room = \"lobby\"\n",
+    );
+    let cli = Cli::try_parse_from([
+        "chat-server",
+        "--config",
+        path.to_str().unwrap(),
+        "--max-msg-len",
+        "200",
+    ])
+    .unwrap();
+    let config = ServerConfig::from_cli(cli).unwrap();
+    assert_eq!(config.bind, vec!["127.0.0.1:9000".parse().unwrap()]);
+    assert_eq!(config.max_msg_len, 200);
+    assert_eq!(config.main_room, "lobby");
+    assert_eq!(config.room_channel_capacity, ServerConfig::default().room_channel_capacity);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn multiple_bind_addresses() {
+    let cli = Cli::try_parse_from(["chat-server", "--bind", "127.0.0.1:1,[::1]:2"]).unwrap();
+    let config = ServerConfig::from_cli(cli).unwrap();
+    assert_eq!(config.bind.len(), 2);
+}
+
+#[test]
+fn unknown_config_keys_are_rejected() {
+    let path = write_config("unknown.toml", "max_msg_length = 100\n");
+    assert!(ServerConfig::from_file(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    for args in [
+        ["chat-server", "--room-channel-capacity", "0"],
+        ["chat-server", "--max-connections", "0"],
+        ["chat-server", "--main-room", "no spaces"],
+        ["chat-server", "--chat-burst", "0"],
+        ["chat-server", "--command-refill-ms", "0"],
+    ] {
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(ServerConfig::from_cli(cli).is_err(), "{args:?}");
+    }
+}
+
+#[tokio::test]
+async fn main_room_and_msg_len_are_applied() {
+    let addr = spawn_server_with(ServerConfig {
+        main_room: "lobby".into(),
+        max_msg_len: 12,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/join lobby").await;
+    assert_eq!(alice.recv().await, "You are in lobby");
+    alice.send("this message is too long").await;
+    alice.recv_until(|line| line == "Messages can only be 12 chars long").await;
+}
+
+#[tokio::test]
+async fn max_connections_turns_away_extra_clients() {
+    let addr = spawn_server_with(ServerConfig {
+        max_connections: 1,
+        ..ServerConfig::default()
+    })
+    .await;
+    let _alice = Client::connect(addr).await;
+    let mut bob = Client::connect_raw(addr).await;
+    assert_eq!(bob.recv().await, "Server is full, try again later");
+}
+
+#[test]
+fn log_options() {
+    let path = write_config("log.toml", "log_rotation = \"hourly\"\nlog_format = \"json\"\n");
+    let cli = Cli::try_parse_from([
+        "chat-server",
+        "--config",
+        path.to_str().unwrap(),
+        "--log-filter",
+        "debug",
+    ])
+    .unwrap();
+    let config = ServerConfig::from_cli(cli).unwrap();
+    assert_eq!(config.log_rotation, LogRotation::Hourly);
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert_eq!(config.log_filter, "debug");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn flags_can_turn_off_require_login() {
+    let path = write_config("login.toml", "require_login = true\n");
+    let config_arg = path.to_str().unwrap();
+    let parse = |args: &[&str]| {
+        let mut argv = vec!["chat-server", "--config", config_arg];
+        argv.extend(args);
+        ServerConfig::from_cli(Cli::try_parse_from(argv).unwrap()).unwrap().require_login
+    };
+    assert!(parse(&[]));
+    assert!(parse(&["--require-login"]));
+    assert!(!parse(&["--require-login=false"]));
+    std::fs::remove_file(&path).unwrap();
+}
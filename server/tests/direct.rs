@@ -0,0 +1,76 @@
+mod common;
+
+use chat_protocol::{ClientFrame, ServerFrame};
+use common::{spawn_server, Client};
+
+#[tokio::test]
+async fn direct_messages_cross_rooms() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    bob.send("/join ops").await;
+    bob.recv_until(|line| line == "You joined ops").await;
+
+    let msg = format!("/msg {}  psst,  over here", bob.name);
+    alice.send(&msg).await;
+    alice.recv_until(|line| line == format!("[to {}] psst,  over here", bob.name)).await;
+    bob.recv_until(|line| line == format!("[from {}] psst,  over here", alice.name)).await;
+}
+
+#[tokio::test]
+async fn direct_messages_to_offline_users_fail() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+
+    alice.send("/msg nobody hello").await;
+    alice.recv_until(|line| line == "nobody is not online").await;
+    alice.send("/msg nobody").await;
+    alice.recv_until(|line| line == "Usage: /msg {user} {text}").await;
+}
+
+#[tokio::test]
+async fn direct_messages_follow_renames() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let old_name = bob.name.clone();
+
+    bob.send("/name bob").await;
+    bob.recv_until(|line| line == "You are now bob").await;
+
+    alice.send(&format!("/msg {old_name} hi")).await;
+    alice.recv_until(|line| line == format!("{old_name} is not online")).await;
+    alice.send("/msg bob hi").await;
+    bob.recv_until(|line| line == format!("[from {}] hi", alice.name)).await;
+}
+
+#[tokio::test]
+async fn direct_messages_are_typed_frames() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    bob.use_json().await;
+
+    alice.send(&format!("/msg {} hi", bob.name)).await;
+    let expected = ServerFrame::Direct {
+        from: alice.name.clone(),
+        to: bob.name.clone(),
+        text: "hi".into(),
+    };
+    bob.recv_frame_until(|frame| *frame == expected).await;
+
+    bob.send_frame(&ClientFrame::Direct { to: "nobody".into(), text: "hi".into() }).await;
+    let error = bob.recv_frame_until(|frame| matches!(frame, ServerFrame::Error { .. })).await;
+    assert_eq!(error, ServerFrame::error("nobody is not online"));
+}
+
+#[test]
+fn guest_names_can_be_messaged() {
+    let names = chat_server::Names::new();
+    let mut name_generator = chat_server::NameGenerator::new();
+    for _ in 0..1000 {
+        let name = names.get_unique(&mut name_generator);
+        assert!(chat_server::valid_name(Some(&name)), "{name}");
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use chat_server::ServerConfig;
+use common::{spawn_server, spawn_server_with, Client};
+
+// the server only notices a reset on its next read or
+// write, so keep asking until the name is free again
+async fn claim_name(client: &mut Client, name: &str) {
+    let renamed = format!("You are now {name}");
+    let taken = format!("{name} is already taken");
+    for _ in 0..50 {
+        client.send(&format!("/name {name}")).await;
+        let reply = client.recv_until(|line| line == renamed || line == taken).await;
+        if reply == renamed {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("{name} was never released");
+}
+
+#[tokio::test]
+async fn aborted_clients_release_their_name_and_room() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    bob.send("/join doomed").await;
+    bob.recv_until(|line| line == "You joined doomed").await;
+    let bob_name = bob.name.clone();
+    bob.abort();
+
+    claim_name(&mut alice, &bob_name).await;
+    alice.send("/rooms").await;
+    alice.send("/topic").await;
+    loop {
+        let line = alice.recv().await;
+        assert!(!line.starts_with("doomed "), "doomed is still open: {line}");
+        if line.starts_with("No topic in ") {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn clients_vanishing_mid_write_are_cleaned_up() {
+    let addr = spawn_server_with(ServerConfig {
+        command_burst: 1000,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    // queue up far more output than the socket buffers
+    // hold, then go away without reading any of it
+    for _ in 0..500 {
+        bob.send("/help").await;
+    }
+    let bob_name = bob.name.clone();
+    bob.abort();
+
+    let left = format!("{bob_name} left");
+    alice.recv_until(|line| line == left).await;
+    alice.send("/users").await;
+    let users = format!("Users - {}", alice.name);
+    alice.recv_until(|line| line == users).await;
+    claim_name(&mut alice, &bob_name).await;
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use std::sync::Arc;
+use chat_protocol::{ClientFrame, ServerFrame};
+use chat_server::{FileStore, MemoryStore, MessageStore, ServerConfig, StoredMsg};
+use common::{spawn_server, spawn_server_with, Client};
+
+#[test]
+fn memory_store_keeps_last_messages_per_room() {
+    let store = MemoryStore::new(2);
+    for text in ["one", "two", "three"] {
+        store.append(StoredMsg::now("main", "alice", Arc::from(text)));
+    }
+    store.append(StoredMsg::now("rust", "bob", Arc::from("hi")));
+
+    let texts: Vec<_> = store.recent("main", 10).into_iter().map(|msg| msg.text).collect();
+    assert_eq!(texts, [Arc::from("two"), Arc::from("three")]);
+    assert_eq!(store.recent("main", 1)[0].text.as_ref(), "three");
+    assert_eq!(store.recent("rust", 10).len(), 1);
+    assert!(store.recent("empty", 10).is_empty());
+}
+
+#[test]
+fn file_store_survives_restarts() {
+    let path = std::env::temp_dir().join(format!("{}-history.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let store = FileStore::open(&path, 10).unwrap();
+        store.append(StoredMsg::now("main", "alice", Arc::from("before restart")));
+    }
+    let store = FileStore::open(&path, 10).unwrap();
+    let recent = store.recent("main", 10);
+    assert_eq!(recent.len(), 1);
+    assert_eq!(recent[0].from, "alice");
+    assert_eq!(recent[0].text.as_ref(), "before restart");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_store_compacts_its_log() {
+    let path = std::env::temp_dir().join(format!("{}-compact.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let store = FileStore::open(&path, 2).unwrap();
+        for n in 0..10 {
+            store.append(StoredMsg::now("main", "alice", Arc::from(n.to_string())));
+        }
+    }
+    let store = FileStore::open(&path, 2).unwrap();
+    assert_eq!(store.recent("main", 10).len(), 2);
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    assert_eq!(lines, 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_store_clears_rooms_for_good() {
+    let path = std::env::temp_dir().join(format!("{}-clear.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let store = FileStore::open(&path, 10).unwrap();
+        store.append(StoredMsg::now("incident", "alice", Arc::from("secret plans")));
+        store.append(StoredMsg::now("main", "alice", Arc::from("hi")));
+        store.clear("incident");
+        assert!(store.recent("incident", 10).is_empty());
+        store.append(StoredMsg::now("main", "bob", Arc::from("hey")));
+    }
+    let store = FileStore::open(&path, 10).unwrap();
+    assert!(store.recent("incident", 10).is_empty());
+    assert_eq!(store.recent("main", 10).len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_store_keeps_what_comes_after_a_clear() {
+    let path = std::env::temp_dir().join(format!("{}-reuse.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let store = FileStore::open(&path, 10).unwrap();
+        store.append(StoredMsg::now("incident", "alice", Arc::from("secret plans")));
+        store.clear("incident");
+        // queued behind the clear, so it has to survive it
+        store.append(StoredMsg::now("incident", "bob", Arc::from("new room")));
+        store.flush().unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(!log.contains("secret plans"), "{log}");
+        assert!(log.contains("new room"), "{log}");
+    }
+    let store = FileStore::open(&path, 10).unwrap();
+    let recent = store.recent("incident", 10);
+    assert_eq!(recent.len(), 1);
+    assert_eq!(recent[0].from, "bob");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn joining_replays_recent_messages() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/join rust").await;
+    alice.recv_until(|line| line == "You joined rust").await;
+    alice.send("anyone here?").await;
+    alice.recv_until(|line| line.ends_with(": anyone here?")).await;
+
+    let mut bob = Client::connect(addr).await;
+    bob.send("/join rust").await;
+    bob.recv_until(|line| line == "History of rust").await;
+    let line = bob.recv().await;
+    assert!(line.ends_with(&format!("{}: anyone here?", alice.name)), "{line}");
+    bob.recv_until(|line| line == "You joined rust").await;
+}
+
+#[tokio::test]
+async fn history_command_limits_count() {
+    let addr = spawn_server_with(ServerConfig {
+        history_replay: 0,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect(addr).await;
+    alice.use_json().await;
+    for text in ["one", "two", "three"] {
+        alice.send_frame(&ClientFrame::Chat { text: text.into() }).await;
+    }
+    alice.recv_frame_until(|frame| matches!(frame, ServerFrame::Chat { text, .. } if text == "three")).await;
+
+    alice.send_frame(&ClientFrame::History { count: Some(2) }).await;
+    let ServerFrame::History { room, messages } = alice.recv_frame().await else {
+        panic!("expected history");
+    };
+    assert_eq!(room, "main");
+    let texts: Vec<_> = messages.iter().map(|msg| msg.text.as_str()).collect();
+    assert_eq!(texts, ["two", "three"]);
+}
+
+#[tokio::test]
+async fn empty_history() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/history").await;
+    assert_eq!(alice.recv().await, "No history in main");
+    alice.send("/history lots").await;
+    assert_eq!(alice.recv().await, "Invalid message count lots");
+}
+
+#[tokio::test]
+async fn private_rooms_take_their_history_with_them() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/join incident").await;
+    alice.recv_until(|line| line == "You joined incident").await;
+    alice.send("/mode +k hunter2").await;
+    alice.recv_until(|line| line.ends_with("set modes of incident to +k")).await;
+    alice.send("the db password is hunter2").await;
+    alice.recv_until(|line| line.ends_with(": the db password is hunter2")).await;
+    alice.send("/join main").await;
+    alice.recv_until(|line| line == "You joined main").await;
+
+    // a fresh room by the same name, without the key
+    let mut bob = Client::connect(addr).await;
+    bob.send("/join incident").await;
+    loop {
+        let line = bob.recv().await;
+        assert_ne!(line, "History of incident");
+        if line == "You joined incident" {
+            break;
+        }
+    }
+    bob.send("/history 500").await;
+    bob.recv_until(|line| line == "No history in incident").await;
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::{spawn_server, Client};
+
+async fn join(client: &mut Client, room: &str) {
+    client.send(&format!("/join {room}")).await;
+    client.recv_until(|line| line == format!("You joined {room}")).await;
+}
+
+#[tokio::test]
+async fn keyed_rooms_need_the_key() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "incident").await;
+    alice.send("/mode +k hunter2").await;
+    alice.recv_until(|line| line.ends_with("set modes of incident to +k")).await;
+
+    bob.send("/join incident").await;
+    bob.recv_until(|line| line == "Can't join incident, it needs the right key").await;
+    bob.send("/join incident hunter3").await;
+    bob.recv_until(|line| line == "Can't join incident, it needs the right key").await;
+    // failed joins leave users where they were
+    bob.send("/users").await;
+    let users = format!("Users - {}", bob.name);
+    bob.recv_until(|line| line == users).await;
+
+    bob.send("/join incident hunter2").await;
+    bob.recv_until(|line| line == "You joined incident").await;
+}
+
+#[tokio::test]
+async fn invites_get_users_into_closed_rooms() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "incident").await;
+    alice.send("/mode +i").await;
+    alice.recv_until(|line| line.ends_with("set modes of incident to +i")).await;
+    alice.send("/mode +k hunter2").await;
+    alice.recv_until(|line| line.ends_with("set modes of incident to +ik")).await;
+
+    bob.send("/invite nobody").await;
+    bob.recv_until(|line| line == "You are not allowed to do that").await;
+
+    alice.send(&format!("/invite {}", bob.name)).await;
+    let invited = format!("Invited {} to incident", bob.name);
+    alice.recv_until(|line| line == invited).await;
+    let notice = format!("{} invited you to incident, /join incident to accept", alice.name);
+    bob.recv_until(|line| line == notice).await;
+    join(&mut bob, "incident").await;
+
+    bob.send("/join main").await;
+    bob.recv_until(|line| line == "You joined main").await;
+    alice.send(&format!("/uninvite {}", bob.name)).await;
+    let uninvited = format!("Uninvited {} from incident", bob.name);
+    alice.recv_until(|line| line == uninvited).await;
+    bob.send("/join incident").await;
+    bob.recv_until(|line| line == "Can't join incident, it is invite only").await;
+}
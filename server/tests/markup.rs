@@ -0,0 +1,45 @@
+mod common;
+
+use chat_protocol::{ServerFrame, Span};
+use common::{spawn_server, Client};
+
+#[tokio::test]
+async fn actions_reach_text_and_json_clients() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    bob.use_json().await;
+
+    alice.send("/me waves *hello*").await;
+    let waves = format!("* {} waves *hello*", alice.name);
+    alice.recv_until(|line| line == waves).await;
+    let frame = bob.recv_frame_until(|frame| matches!(frame, ServerFrame::Action { .. })).await;
+    let ServerFrame::Action { from, text, spans, .. } = frame else { unreachable!() };
+    assert_eq!(from, alice.name);
+    assert_eq!(text, "waves *hello*");
+    assert_eq!(spans, [Span::Plain { text: "waves ".into() }, Span::Bold { text: "hello".into() }]);
+
+    alice.send("/me").await;
+    alice.recv_until(|line| line == "Usage: /me {action}").await;
+}
+
+#[tokio::test]
+async fn history_keeps_actions_and_markup() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("/me shrugs").await;
+    alice.send("try `cargo test`").await;
+    alice.recv_until(|line| line.ends_with(": try `cargo test`")).await;
+
+    alice.use_json().await;
+    alice.send(r#"{"type":"history","count":2}"#).await;
+    let frame = alice.recv_frame_until(|frame| matches!(frame, ServerFrame::History { .. })).await;
+    let ServerFrame::History { messages, .. } = frame else { unreachable!() };
+    assert!(messages[0].action);
+    assert!(!messages[1].action);
+    assert_eq!(messages[1].spans[1], Span::Code { text: "cargo test".into() });
+
+    let mut bob = Client::connect_raw(addr).await;
+    let shrugs = format!("* {} shrugs", alice.name);
+    bob.recv_until(|line| line.ends_with(&shrugs)).await;
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use chat_protocol::ServerFrame;
+use common::{spawn_server, Client};
+
+#[tokio::test]
+async fn mentions_are_flagged_for_the_mentioned_user_only() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let mut carol = Client::connect(addr).await;
+    bob.use_json().await;
+    carol.use_json().await;
+
+    alice.send(&format!("@{}: ping, @nobody", bob.name)).await;
+    let is_chat = |frame: &ServerFrame| matches!(frame, ServerFrame::Chat { .. });
+    let frame = bob.recv_frame_until(is_chat).await;
+    assert!(matches!(frame, ServerFrame::Chat { mention: true, .. }), "{frame:?}");
+    let frame = carol.recv_frame_until(is_chat).await;
+    assert!(matches!(frame, ServerFrame::Chat { mention: false, .. }), "{frame:?}");
+
+    // actions count too
+    alice.send(&format!("/me pokes @{}", carol.name)).await;
+    let frame = carol.recv_frame_until(|frame| matches!(frame, ServerFrame::Action { .. })).await;
+    assert!(matches!(frame, ServerFrame::Action { mention: true, .. }), "{frame:?}");
+}
+
+#[tokio::test]
+async fn mentions_reach_users_in_other_rooms() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    bob.send("/join ops").await;
+    bob.recv_until(|line| line == "You joined ops").await;
+
+    let text = format!("@{} could you look at the deploy?", bob.name);
+    alice.send(&text).await;
+    let notification = format!("{} mentioned you in main: {text}", alice.name);
+    bob.recv_until(|line| line == notification).await;
+
+    // and away users still answer from there
+    bob.send("/away on call").await;
+    bob.recv_until(|line| line == "You are away: on call").await;
+    alice.send(&text).await;
+    let away = format!("{} is away: on call", bob.name);
+    alice.recv_until(|line| line == away).await;
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use chat_server::{serve, ServerConfig};
+use common::Client;
+
+async fn spawn_metrics_server() -> (SocketAddr, SocketAddr) {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let metrics_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = (server.local_addr().unwrap(), metrics_server.local_addr().unwrap());
+    tokio::spawn(serve(vec![server], Vec::new(), vec![metrics_server], ServerConfig::default()));
+    addrs
+}
+
+async fn get(addr: SocketAddr, request: &str) -> String {
+    let mut conn = TcpStream::connect(addr).await.unwrap();
+    conn.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn metrics_count_users_and_messages() {
+    let (addr, metrics_addr) = spawn_metrics_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.send("hello").await;
+    let hello = format!("{}: hello", alice.name);
+    alice.recv_until(|line| line == hello).await;
+    alice.send("/rooms").await;
+    alice.recv_until(|line| line == "Rooms").await;
+    alice.send("/users").await;
+    alice.recv_until(|line| line.starts_with("Users - ")).await;
+
+    let response = get(metrics_addr, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"), "{response}");
+    let lines: Vec<&str> = response.lines().collect();
+    for expected in [
+        "chat_users 1",
+        "chat_connections 1",
+        "chat_connections_total 1",
+        "chat_rooms 1",
+        "chat_room_receivers{room=\"main\"} 1",
+        "chat_messages_total 1",
+        "chat_commands_total{command=\"rooms\"} 1",
+        "chat_commands_total{command=\"users\"} 1",
+        "# TYPE chat_messages_total counter",
+    ] {
+        assert!(lines.contains(&expected), "missing {expected:?} in {response}");
+    }
+}
+
+#[tokio::test]
+async fn healthz_and_unknown_paths() {
+    let (_, metrics_addr) = spawn_metrics_server().await;
+    let response = get(metrics_addr, "GET /healthz HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nok\n"), "{response}");
+
+    let response = get(metrics_addr, "GET /nope HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+    let response = get(metrics_addr, "POST /metrics HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 405 "), "{response}");
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{spawn_server, Client};
+
+async fn join(client: &mut Client, room: &str) {
+    client.send(&format!("/join {room}")).await;
+    client.recv_until(|line| line == format!("You joined {room}")).await;
+}
+
+#[tokio::test]
+async fn owners_kick_users_back_to_main() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "ops").await;
+    join(&mut bob, "ops").await;
+
+    bob.send(&format!("/kick {}", alice.name)).await;
+    bob.recv_until(|line| line == "You are not allowed to do that").await;
+
+    alice.send(&format!("/kick {} stop spamming", bob.name)).await;
+    let kicked = format!("{} was kicked by {} (stop spamming)", bob.name, alice.name);
+    alice.recv_until(|line| line == kicked).await;
+    bob.recv_until(|line| line == format!("You were kicked from ops by {}: stop spamming", alice.name)).await;
+    bob.recv_until(|line| line == "You joined main").await;
+    join(&mut bob, "ops").await;
+}
+
+#[tokio::test]
+async fn operators_rank_below_owners() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let mut carol = Client::connect(addr).await;
+    join(&mut alice, "ops").await;
+    join(&mut bob, "ops").await;
+    join(&mut carol, "ops").await;
+
+    alice.send(&format!("/op {}", bob.name)).await;
+    bob.recv_until(|line| line == format!("{} made you operator", alice.name)).await;
+    bob.send(&format!("/op {}", carol.name)).await;
+    bob.recv_until(|line| line == "You are not allowed to do that").await;
+    bob.send(&format!("/kick {}", alice.name)).await;
+    bob.recv_until(|line| line == "You are not allowed to do that").await;
+    bob.send(&format!("/voice {}", carol.name)).await;
+    carol.recv_until(|line| line == format!("{} made you voice", bob.name)).await;
+
+    alice.send(&format!("/deop {}", bob.name)).await;
+    bob.recv_until(|line| line == format!("{} made you member", alice.name)).await;
+    bob.send(&format!("/kick {}", carol.name)).await;
+    bob.recv_until(|line| line == "You are not allowed to do that").await;
+}
+
+#[tokio::test]
+async fn banned_users_cant_rejoin() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "ops").await;
+    join(&mut bob, "ops").await;
+
+    alice.send(&format!("/ban {}", bob.name)).await;
+    alice.recv_until(|line| line == format!("Banned {} from ops", bob.name)).await;
+    bob.recv_until(|line| line == format!("You were kicked from ops by {}: banned", alice.name)).await;
+    bob.recv_until(|line| line == "You joined main").await;
+    bob.send("/join ops").await;
+    bob.recv_until(|line| line == "Can't join ops, you are banned").await;
+    bob.send("hello main").await;
+    let echo = format!("{}: hello main", bob.name);
+    bob.recv_until(|line| line == echo).await;
+
+    alice.send(&format!("/unban {}", bob.name)).await;
+    alice.recv_until(|line| line == format!("Unbanned {} from ops", bob.name)).await;
+    alice.send(&format!("/unban {}", bob.name)).await;
+    alice.recv_until(|line| line == format!("{} is not banned", bob.name)).await;
+    join(&mut bob, "ops").await;
+}
+
+#[tokio::test]
+async fn ip_bans_cover_everyone_on_the_address() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let mut carol = Client::connect(addr).await;
+    join(&mut alice, "ops").await;
+    join(&mut bob, "ops").await;
+    join(&mut carol, "ops").await;
+
+    alice.send("/ban 127.0.0.1").await;
+    alice.recv_until(|line| line == "Banned 127.0.0.1 from ops").await;
+    for client in [&mut bob, &mut carol] {
+        client.recv_until(|line| line == "You joined main").await;
+        client.send("/join ops").await;
+        client.recv_until(|line| line == "Can't join ops, you are banned").await;
+    }
+}
+
+#[tokio::test]
+async fn nobody_is_kicked_from_main() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    alice.send(&format!("/kick {}", bob.name)).await;
+    alice.recv_until(|line| line == "Nobody can be kicked from main").await;
+
+    // bob is still connected and still in main
+    bob.send("still here").await;
+    alice.recv_until(|line| line.ends_with(&format!("{}: still here", bob.name))).await;
+}
+
+#[tokio::test]
+async fn nobody_owns_main() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let bob = Client::connect(addr).await;
+
+    alice.send(&format!("/op {}", bob.name)).await;
+    alice.recv_until(|line| line == "You are not allowed to do that").await;
+}
+
+#[tokio::test]
+async fn nobody_is_banned_from_main() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    alice.send(&format!("/ban {}", bob.name)).await;
+    alice.recv_until(|line| line == "Nobody can be banned from main").await;
+    alice.send("/ban 127.0.0.1").await;
+    alice.recv_until(|line| line == "Nobody can be banned from main").await;
+
+    // bob can still come back after leaving
+    bob.send("/join ops").await;
+    bob.recv_until(|line| line == "You joined ops").await;
+    join(&mut bob, "main").await;
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use chat_server::{Rooms, ServerConfig};
+use common::{spawn_server_with, Client};
+use tokio::task::JoinSet;
+
+const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+#[test]
+fn idle_time_follows_users_between_rooms() {
+    let rooms = Rooms::new();
+    let start = Instant::now();
+    rooms.join("main", "alice", IP, None).unwrap();
+    rooms.join("main", "bob", IP, None).unwrap();
+    rooms.touch("main", "bob", start + Duration::from_secs(100));
+
+    let users = rooms.list_users("main", start + Duration::from_secs(400));
+    let names: Vec<_> = users.iter().map(|user| user.name.as_str()).collect();
+    assert_eq!(names, ["alice", "bob"]);
+    assert!(users[0].idle_secs >= 399);
+    assert_eq!(users[1].idle_secs, 300);
+
+    rooms.change("main", "rust", "bob", IP, None).unwrap();
+    let users = rooms.list_users("rust", start + Duration::from_secs(400));
+    assert_eq!(users[0].idle_secs, 300);
+    assert_eq!(rooms.list_users("main", Instant::now()).len(), 1);
+    assert!(rooms.list_users("nowhere", Instant::now()).is_empty());
+}
+
+#[test]
+fn rooms_go_away_with_their_last_user() {
+    let rooms = Rooms::new();
+    rooms.join("rust", "alice", IP, None).unwrap();
+    // holding on to a subscription doesn't keep it open
+    let tx = rooms.join("rust", "bob", IP, None).unwrap();
+    let _rx = tx.subscribe();
+    rooms.leave("rust", "alice");
+    assert_eq!(rooms.list("alice")[0].users, 1);
+    rooms.leave("rust", "bob");
+    assert!(rooms.list("bob").is_empty());
+}
+
+#[test]
+fn concurrent_room_hopping_leaves_no_ghost_rooms() {
+    let rooms = Rooms::new();
+    std::thread::scope(|scope| {
+        for n in 0..8 {
+            let rooms = &rooms;
+            scope.spawn(move || {
+                let name = format!("user{n}");
+                let mut current = String::from("main");
+                rooms.join(&current, &name, IP, None).unwrap();
+                for i in 0..2000 {
+                    let next = format!("room{}", (n + i) % 4);
+                    if next != current {
+                        rooms.change(&current, &next, &name, IP, None).unwrap();
+                        current = next;
+                    }
+                    let users = rooms.list_users(&current, Instant::now());
+                    assert!(users.iter().any(|user| user.name == name));
+                    for room in rooms.list(&name) {
+                        assert!(room.users > 0, "{} is listed empty", room.name);
+                    }
+                }
+                rooms.leave(&current, &name);
+            });
+        }
+    });
+    assert!(rooms.list("").is_empty());
+    assert!(rooms.receiver_counts().is_empty());
+}
+
+// the lines of a /rooms reply, after the "Rooms" header
+async fn list_rooms(client: &mut Client) -> Vec<String> {
+    client.send("/rooms").await;
+    client.send("/topic").await;
+    client.recv_until(|line| line == "Rooms").await;
+    let mut rooms = Vec::new();
+    loop {
+        let line = client.recv().await;
+        if line.starts_with("No topic in ") {
+            return rooms;
+        }
+        rooms.push(line);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn room_hopping_under_load() {
+    let addr = spawn_server_with(ServerConfig {
+        command_burst: 10_000,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut clients = JoinSet::new();
+    for n in 0..16 {
+        clients.spawn(async move {
+            let mut client = Client::connect(addr).await;
+            for i in 0..30 {
+                client.send(&format!("/join room{}", (n + i) % 3)).await;
+                client.send("/users").await;
+                client.send("/rooms").await;
+            }
+            // the session is still answering and
+            // lists its own user where it ended up
+            client.send(&format!("/join solo{n}")).await;
+            client.recv_until(|line| line == format!("You joined solo{n}")).await;
+            client.send("/users").await;
+            let users = format!("Users - {}", client.name);
+            client.recv_until(|line| line == users).await;
+            if n % 2 == 0 {
+                client.abort();
+            } else {
+                client.send("/quit").await;
+                client.recv_closed().await;
+            }
+        });
+    }
+    while let Some(client) = clients.join_next().await {
+        client.unwrap();
+    }
+
+    let mut observer = Client::connect(addr).await;
+    let expected = vec!["main (1)".to_owned()];
+    for _ in 0..50 {
+        if list_rooms(&mut observer).await == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("ghost rooms left: {:?}", list_rooms(&mut observer).await);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use chat_protocol::{ClientFrame, Role, ServerFrame, Span};
+use common::{spawn_server, Client};
+
+#[tokio::test]
+async fn json_and_text_clients_share_rooms() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    alice.use_json().await;
+
+    alice.send_frame(&ClientFrame::Chat { text: "hi bob".into() }).await;
+    bob.recv_until(|line| line == format!("{}: hi bob", alice.name)).await;
+
+    bob.send("hi alice").await;
+    let expected = ServerFrame::Chat {
+        from: bob.name.clone(),
+        text: "hi alice".into(),
+        spans: vec![Span::Plain { text: "hi alice".into() }],
+        mention: false,
+    };
+    alice.recv_frame_until(|frame| *frame == expected).await;
+}
+
+#[tokio::test]
+async fn json_commands_get_typed_replies() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.use_json().await;
+
+    alice.send_frame(&ClientFrame::Join { room: "rust".into(), key: None }).await;
+    let frame = alice.recv_frame_until(|frame| matches!(frame, ServerFrame::Joined { .. })).await;
+    assert_eq!(frame, ServerFrame::Joined { room: "rust".into(), user: alice.name.clone() });
+
+    alice.send_frame(&ClientFrame::Users).await;
+    let ServerFrame::Users { room, users } = alice.recv_frame().await else {
+        panic!("expected a users frame");
+    };
+    assert_eq!(room, "rust");
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].name, alice.name);
+    assert_eq!(users[0].role, Role::Owner);
+    assert_eq!(users[0].idle_secs, 0);
+
+    alice.send_frame(&ClientFrame::Rename { name: "x".into() }).await;
+    assert!(matches!(alice.recv_frame().await, ServerFrame::Error { .. }));
+}
+
+#[tokio::test]
+async fn invalid_json_is_reported() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.use_json().await;
+
+    alice.send("not json").await;
+    assert!(matches!(alice.recv_frame().await, ServerFrame::Error { .. }));
+}
+
+#[tokio::test]
+async fn unsupported_version_keeps_text_protocol() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+
+    alice.send("/protocol json 99").await;
+    alice.recv_until(|line| line.starts_with("Unsupported protocol version 99")).await;
+    alice.send("/join rust").await;
+    alice.recv_until(|line| line == "You joined rust").await;
+}
+
+#[tokio::test]
+async fn json_clients_can_switch_back_to_text() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    alice.use_json().await;
+
+    alice.send(r#"{"type":"protocol","format":"text","version":1}"#).await;
+    alice.recv_until(|line| line == "Using text protocol").await;
+}
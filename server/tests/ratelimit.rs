@@ -0,0 +1,109 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use chat_server::{LimiterStats, RateLimiter, ServerConfig, TokenBucket, Traffic, Verdict};
+use common::{spawn_server, spawn_server_with, Client};
+
+#[test]
+fn buckets_refill_over_time() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(2, Duration::from_secs(1), start);
+    assert!(bucket.try_take(start));
+    assert!(bucket.try_take(start));
+    assert!(!bucket.try_take(start));
+    assert!(!bucket.try_take(start + Duration::from_millis(999)));
+    assert!(bucket.try_take(start + Duration::from_millis(1000)));
+    // never holds more than the burst
+    let later = start + Duration::from_secs(60);
+    assert!(bucket.try_take(later));
+    assert!(bucket.try_take(later));
+    assert!(!bucket.try_take(later));
+}
+
+#[test]
+fn long_idles_dont_overflow() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(u32::MAX, Duration::from_nanos(1), start);
+    assert!(bucket.try_take(start));
+    // earns far more than u32::MAX tokens back
+    let later = start + Duration::from_secs(3600);
+    assert!(bucket.try_take(later));
+    assert!(bucket.try_take(later));
+}
+
+#[test]
+fn flooding_escalates() {
+    let config = ServerConfig {
+        chat_burst: 1,
+        command_burst: 1,
+        flood_mute_secs: 10,
+        ..ServerConfig::default()
+    };
+    let stats = Arc::new(LimiterStats::default());
+    let mut limiter = RateLimiter::new(&config, stats.clone());
+    let now = Instant::now();
+    assert_eq!(limiter.check(Traffic::Chat, now), Verdict::Allow);
+    assert_eq!(limiter.check(Traffic::Chat, now), Verdict::Warn);
+    assert_eq!(limiter.check(Traffic::Chat, now), Verdict::Mute(Duration::from_secs(10)));
+    assert_eq!(limiter.check(Traffic::Chat, now), Verdict::Muted(Duration::from_secs(10)));
+    // muted users can still use commands
+    assert_eq!(limiter.check(Traffic::Command, now), Verdict::Allow);
+    assert_eq!(limiter.check(Traffic::Command, now), Verdict::Disconnect);
+
+    assert_eq!(stats.dropped.load(Ordering::Relaxed), 4);
+    assert_eq!(stats.warned.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.muted.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.disconnected.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn flooders_get_disconnected() {
+    let addr = spawn_server_with(ServerConfig {
+        chat_burst: 2,
+        chat_refill_ms: 60_000,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect(addr).await;
+    for n in 0..5 {
+        alice.send(&format!("spam {n}")).await;
+    }
+    alice.recv_until(|line| line == "You are sending too fast, slow down").await;
+    alice.recv_until(|line| line.starts_with("You are muted for ")).await;
+    alice.recv_until(|line| line.starts_with("You are muted for another ")).await;
+
+    alice.send("/rooms").await;
+    alice.recv_until(|line| line == "Rooms").await;
+    for _ in 0..20 {
+        alice.send("/rooms").await;
+    }
+    alice.recv_until(|line| line == "Disconnected for flooding").await;
+    alice.recv_closed().await;
+}
+
+#[tokio::test]
+async fn slow_mode_spaces_out_messages() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    alice.send("/join lobby").await;
+    alice.recv_until(|line| line == "You joined lobby").await;
+    bob.send("/join lobby").await;
+    bob.recv_until(|line| line == "You joined lobby").await;
+    alice.send("/mode +d 60").await;
+    alice.recv_until(|line| line.ends_with("set modes of lobby to +d 60")).await;
+
+    bob.send("first").await;
+    let first = format!("{}: first", bob.name);
+    bob.recv_until(|line| line == first).await;
+    bob.send("second").await;
+    bob.recv_until(|line| line.starts_with("lobby is in slow mode, wait ")).await;
+
+    // operators aren't slowed down
+    alice.send("one").await;
+    alice.send("two").await;
+    let two = format!("{}: two", alice.name);
+    bob.recv_until(|line| line == two).await;
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{spawn_server, Client};
+
+#[tokio::test]
+async fn rename_updates_name_everywhere() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let old_name = alice.name.clone();
+
+    alice.send("/name alice").await;
+    alice.recv_until(|line| line == "You are now alice").await;
+    bob.recv_until(|line| line == format!("{old_name} is now alice")).await;
+
+    alice.send("hello").await;
+    bob.recv_until(|line| line == "alice: hello").await;
+
+    bob.send("/users").await;
+    let users = bob.recv_until(|line| line.starts_with("Users - ")).await;
+    assert!(users.contains("alice"));
+    assert!(!users.contains(&old_name));
+}
+
+#[tokio::test]
+async fn rename_to_taken_name_is_rejected() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let old_name = bob.name.clone();
+
+    alice.send("/name alice").await;
+    alice.recv_until(|line| line == "You are now alice").await;
+
+    bob.send("/name alice").await;
+    bob.recv_until(|line| line == "alice is already taken").await;
+
+    bob.send("hi").await;
+    alice.recv_until(|line| line == format!("{old_name}: hi")).await;
+}
+
+#[tokio::test]
+async fn rename_releases_old_name() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    alice.send("/name alice").await;
+    alice.recv_until(|line| line == "You are now alice").await;
+    alice.send("/name alicia").await;
+    alice.recv_until(|line| line == "You are now alicia").await;
+
+    bob.send("/name alice").await;
+    bob.recv_until(|line| line == "You are now alice").await;
+}
+
+#[tokio::test]
+async fn rename_to_current_name_is_a_noop() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+
+    alice.send("/name alice").await;
+    alice.recv_until(|line| line == "You are now alice").await;
+    alice.send("/name alice").await;
+    assert_eq!(alice.recv().await, "You are alice");
+}
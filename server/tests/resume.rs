@@ -0,0 +1,110 @@
+mod common;
+
+use std::time::Duration;
+use chat_protocol::{ClientFrame, ServerFrame};
+use chat_server::ServerConfig;
+use common::{spawn_server, spawn_server_with, Client};
+
+// switches to json and returns the resume token
+async fn token(client: &mut Client) -> String {
+    client.send("/protocol json").await;
+    let welcome = client.recv_until(|line| line.starts_with('{')).await;
+    let ServerFrame::Welcome { resume: Some(token), .. } = serde_json::from_str(&welcome).unwrap() else {
+        panic!("no resume token in {welcome}");
+    };
+    token
+}
+
+// the server parks a session once it notices the
+// connection is gone, which takes a moment
+async fn resume(client: &mut Client, token: &str) -> ServerFrame {
+    for _ in 0..50 {
+        client.send_frame(&ClientFrame::Resume { token: token.into() }).await;
+        let frame = client
+            .recv_frame_until(|frame| matches!(frame, ServerFrame::Welcome { .. } | ServerFrame::Error { .. }))
+            .await;
+        if matches!(frame, ServerFrame::Welcome { .. }) {
+            return frame;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("session was never parked");
+}
+
+#[tokio::test]
+async fn dropped_sessions_resume_with_missed_messages() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    alice.send("/join den").await;
+    alice.recv_until(|line| line == "You joined den").await;
+    bob.send("/join den").await;
+    bob.recv_until(|line| line == "You joined den").await;
+    let alice_name = alice.name.clone();
+    let token = token(&mut alice).await;
+    alice.abort();
+
+    // still online as far as everyone else can tell
+    bob.send("while you were out").await;
+    bob.send(&format!("/msg {alice_name} psst")).await;
+    let echo = format!("[to {alice_name}] psst");
+    bob.recv_until(|line| line == echo).await;
+
+    let mut again = Client::connect(addr).await;
+    again.use_json().await;
+    let ServerFrame::Welcome { name, room, resume: new_token, .. } = resume(&mut again, &token).await else {
+        unreachable!()
+    };
+    assert_eq!(name, alice_name);
+    assert_eq!(room, "den");
+    assert!(new_token.is_some_and(|new_token| new_token != token));
+    // room and direct messages can come in either order
+    let (mut chat, mut direct) = (false, false);
+    while !(chat && direct) {
+        match again.recv_frame().await {
+            ServerFrame::Chat { text, .. } => chat |= text == "while you were out",
+            ServerFrame::Direct { text, .. } => direct |= text == "psst",
+            _ => (),
+        }
+    }
+
+    // tokens only work once
+    let mut thief = Client::connect(addr).await;
+    thief.use_json().await;
+    thief.send_frame(&ClientFrame::Resume { token }).await;
+    let frame = thief.recv_frame_until(|frame| matches!(frame, ServerFrame::Error { .. })).await;
+    assert!(matches!(frame, ServerFrame::Error { text } if text == "Can't resume, the session has expired"));
+}
+
+#[tokio::test]
+async fn expired_sessions_are_released() {
+    let addr = spawn_server_with(ServerConfig {
+        resume_grace_secs: 1,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let alice_name = alice.name.clone();
+    let token = token(&mut alice).await;
+    alice.abort();
+
+    let left = format!("{alice_name} left");
+    bob.recv_until(|line| line == left).await;
+    bob.send(&format!("/name {alice_name}")).await;
+    let renamed = format!("You are now {alice_name}");
+    bob.recv_until(|line| line == renamed).await;
+
+    bob.send(&format!("/resume {token}")).await;
+    bob.recv_until(|line| line == "Can't resume, the session has expired").await;
+}
+
+#[tokio::test]
+async fn text_sessions_are_not_parked() {
+    let addr = spawn_server().await;
+    let alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    let left = format!("{} left", alice.name);
+    alice.abort();
+    bob.recv_until(|line| line == left).await;
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use chat_server::{serve_with_shutdown, ServerConfig};
+use common::Client;
+
+struct Server {
+    addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<Result<(), io::Error>>,
+}
+
+async fn spawn_stoppable_server(config: ServerConfig) -> Server {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel();
+    let shutdown = async {
+        let _ = stopped.await;
+    };
+    let handle = tokio::spawn(serve_with_shutdown(vec![server], Vec::new(), Vec::new(), config, shutdown));
+    Server { addr, stop, handle }
+}
+
+async fn stopped(handle: JoinHandle<Result<(), io::Error>>) {
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("server didn't stop in time")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn users_are_warned_then_disconnected() {
+    let server = spawn_stoppable_server(ServerConfig {
+        shutdown_grace_secs: 1,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut alice = Client::connect(server.addr).await;
+    let mut bob = Client::connect(server.addr).await;
+    bob.send("/join lobby").await;
+    bob.recv_until(|line| line == "You joined lobby").await;
+
+    server.stop.send(()).unwrap();
+    for client in [&mut alice, &mut bob] {
+        client.recv_until(|line| line == "Server is shutting down in 1s").await;
+    }
+    // new connections are refused during the grace period
+    assert!(TcpStream::connect(server.addr).await.is_err());
+    // but everybody already in can still talk
+    alice.send("bye all").await;
+    let bye = format!("{}: bye all", alice.name);
+    alice.recv_until(|line| line == bye).await;
+
+    for client in [&mut alice, &mut bob] {
+        client.recv_until(|line| line == "Server is shutting down, bye!").await;
+        client.recv_closed().await;
+    }
+    stopped(server.handle).await;
+}
+
+#[tokio::test]
+async fn users_waiting_to_log_in_are_disconnected() {
+    let server = spawn_stoppable_server(ServerConfig {
+        require_login: true,
+        shutdown_grace_secs: 1,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut guest = Client::connect_raw(server.addr).await;
+    guest.recv_until(|line| line.starts_with("Log in with ")).await;
+
+    server.stop.send(()).unwrap();
+    guest.recv_until(|line| line == "Server is shutting down, bye!").await;
+    guest.recv_closed().await;
+    stopped(server.handle).await;
+}
+
+#[tokio::test]
+async fn idle_servers_stop_right_away() {
+    let server = spawn_stoppable_server(ServerConfig {
+        shutdown_grace_secs: 60,
+        ..ServerConfig::default()
+    })
+    .await;
+    server.stop.send(()).unwrap();
+    stopped(server.handle).await;
+}
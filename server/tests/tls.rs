@@ -0,0 +1,91 @@
+mod common;
+
+use std::sync::Arc;
+use futures::{SinkExt, StreamExt};
+use rustls::pki_types::ServerName;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+use chat_server::{load_certs, ServerConfig};
+use common::spawn_server_with;
+
+fn write_cert(file_name: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+    let rcgen::CertifiedKey { cert, signing_key } =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("{}-{file_name}.crt", std::process::id()));
+    let key_path = dir.join(format!("{}-{file_name}.key", std::process::id()));
+    std::fs::write(&cert_path, cert.pem()).unwrap();
+    std::fs::write(&key_path, signing_key.serialize_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+#[tokio::test]
+async fn tls_connections_run_the_same_session() {
+    let (cert_path, key_path) = write_cert("session");
+    let addr = spawn_server_with(ServerConfig {
+        tls_cert: Some(cert_path.clone()),
+        tls_key: Some(key_path.clone()),
+        ..ServerConfig::default()
+    })
+    .await;
+
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in load_certs(&cert_path).unwrap() {
+        roots.add(cert).unwrap();
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let tls = connector.connect(server_name, tcp).await.unwrap();
+
+    let (reader, writer) = tokio::io::split(tls);
+    let mut stream = FramedRead::new(reader, LinesCodec::new());
+    let mut sink = FramedWrite::new(writer, LinesCodec::new());
+    let mut next = async || stream.next().await.unwrap().unwrap();
+    while !next().await.starts_with("You are ") {}
+    sink.send("/join secure").await.unwrap();
+    while next().await != "You joined secure" {}
+    std::fs::remove_file(&cert_path).unwrap();
+    std::fs::remove_file(&key_path).unwrap();
+}
+
+#[tokio::test]
+async fn plain_clients_cannot_talk_to_tls_servers() {
+    let (cert_path, key_path) = write_cert("plain");
+    let addr = spawn_server_with(ServerConfig {
+        tls_cert: Some(cert_path.clone()),
+        tls_key: Some(key_path.clone()),
+        ..ServerConfig::default()
+    })
+    .await;
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (reader, writer) = tcp.into_split();
+    let mut sink = FramedWrite::new(writer, LinesCodec::new());
+    let mut stream = FramedRead::new(reader, LinesCodec::new());
+    let _ = sink.send("/help").await;
+    // the handshake fails and the server hangs up without
+    // ever sending a plain text line
+    let line = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+        .await
+        .unwrap();
+    assert!(!matches!(line, Some(Ok(line)) if line.starts_with("You are")));
+    std::fs::remove_file(&cert_path).unwrap();
+    std::fs::remove_file(&key_path).unwrap();
+}
+
+#[test]
+fn tls_cert_and_key_go_together() {
+    let config = ServerConfig {
+        tls_cert: Some("server.crt".into()),
+        ..ServerConfig::default()
+    };
+    assert!(config.validate().is_err());
+}
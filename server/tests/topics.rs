@@ -0,0 +1,115 @@
+mod common;
+
+use common::{spawn_server, Client};
+
+async fn join(client: &mut Client, room: &str) {
+    client.send(&format!("/join {room}")).await;
+    client.recv_until(|line| line == format!("You joined {room}")).await;
+}
+
+#[tokio::test]
+async fn topics_are_shown_on_join() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "rust").await;
+
+    alice.send("/topic").await;
+    alice.recv_until(|line| line == "No topic in rust").await;
+    alice.send("/topic all things crab").await;
+    let set = format!("{} set the topic to: all things crab", alice.name);
+    alice.recv_until(|line| line == set).await;
+
+    bob.send("/join rust").await;
+    bob.recv_until(|line| line == "Topic of rust: all things crab").await;
+    bob.send("/topic mine now").await;
+    bob.recv_until(|line| line == "You are not allowed to do that").await;
+
+    bob.send("/rooms").await;
+    bob.recv_until(|line| line == "rust (2) - all things crab").await;
+}
+
+#[tokio::test]
+async fn secret_rooms_are_hidden() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "hideout").await;
+    alice.send("/mode +s").await;
+    let set = format!("{} set modes of hideout to +s", alice.name);
+    alice.recv_until(|line| line == set).await;
+
+    alice.send("/rooms").await;
+    alice.recv_until(|line| line == "hideout (1) +s").await;
+    // text frames span a line per room, the topic
+    // request marks where the listing ends
+    bob.send("/rooms").await;
+    bob.send("/topic").await;
+    bob.recv_until(|line| line == "Rooms").await;
+    loop {
+        let line = bob.recv().await;
+        assert!(!line.contains("hideout"), "{line}");
+        if line == "No topic in main" {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn moderated_rooms_need_voice() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "stage").await;
+    join(&mut bob, "stage").await;
+    alice.send("/mode +m").await;
+    bob.recv_until(|line| line.ends_with("set modes of stage to +m")).await;
+
+    bob.send("hello?").await;
+    bob.recv_until(|line| line == "stage is moderated, only voiced users can talk").await;
+    alice.send(&format!("/voice {}", bob.name)).await;
+    bob.recv_until(|line| line.ends_with("made you voice")).await;
+    bob.send("hello!").await;
+    let echo = format!("{}: hello!", bob.name);
+    alice.recv_until(|line| line == echo).await;
+}
+
+#[tokio::test]
+async fn limited_and_invite_only_rooms_turn_users_away() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+    join(&mut alice, "tiny").await;
+
+    alice.send("/mode +l 1").await;
+    alice.recv_until(|line| line.ends_with("set modes of tiny to +l 1")).await;
+    bob.send("/join tiny").await;
+    bob.recv_until(|line| line == "Can't join tiny, it is full").await;
+
+    alice.send("/mode -l").await;
+    alice.recv_until(|line| line.ends_with("set modes of tiny to none")).await;
+    alice.send("/mode +i").await;
+    alice.recv_until(|line| line.ends_with("set modes of tiny to +i")).await;
+    bob.send("/join tiny").await;
+    bob.recv_until(|line| line == "Can't join tiny, it is invite only").await;
+    bob.send("/mode").await;
+    bob.recv_until(|line| line == "Modes of main: none").await;
+    bob.send("/mode +i").await;
+    bob.recv_until(|line| line == "main modes can't be changed").await;
+}
+
+#[tokio::test]
+async fn main_modes_cant_be_changed() {
+    let addr = spawn_server().await;
+    let mut alice = Client::connect(addr).await;
+    let mut bob = Client::connect(addr).await;
+
+    alice.send("/mode +m").await;
+    alice.recv_until(|line| line == "main modes can't be changed").await;
+    alice.send("/mode").await;
+    alice.recv_until(|line| line == "Modes of main: none").await;
+
+    // nobody was muted by it
+    bob.send("still talking").await;
+    alice.recv_until(|line| line.ends_with(&format!("{}: still talking", bob.name))).await;
+}
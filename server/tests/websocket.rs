@@ -0,0 +1,84 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use chat_server::{serve, ServerConfig, INDEX_HTML};
+
+async fn spawn_ws_server() -> (SocketAddr, SocketAddr) {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let ws_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = (server.local_addr().unwrap(), ws_server.local_addr().unwrap());
+    tokio::spawn(serve(vec![server], vec![ws_server], Vec::new(), ServerConfig::default()));
+    addrs
+}
+
+async fn next_text<S>(ws: &mut S, pred: impl Fn(&str) -> bool) -> String
+where
+    S: futures::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(5), ws.next())
+            .await
+            .expect("timed out waiting for a message")
+            .unwrap()
+            .unwrap();
+        if let Message::Text(text) = msg
+            && pred(text.as_str())
+        {
+            return text.as_str().to_owned();
+        }
+    }
+}
+
+#[tokio::test]
+async fn websocket_and_tcp_users_share_rooms() {
+    let (addr, ws_addr) = spawn_ws_server().await;
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{ws_addr}/")).await.unwrap();
+    let welcome = next_text(&mut ws, |text| text.starts_with("You are ")).await;
+    let ws_name = welcome["You are ".len()..].to_owned();
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (reader, writer) = tcp.into_split();
+    let mut lines = tokio_util::codec::FramedRead::new(reader, tokio_util::codec::LinesCodec::new());
+    let mut sink = tokio_util::codec::FramedWrite::new(writer, tokio_util::codec::LinesCodec::new());
+    while !lines.next().await.unwrap().unwrap().starts_with("You joined ") {}
+
+    ws.send(Message::text("hello from the browser")).await.unwrap();
+    let expected = format!("{ws_name}: hello from the browser");
+    while lines.next().await.unwrap().unwrap() != expected {}
+
+    sink.send("hello from tcp").await.unwrap();
+    next_text(&mut ws, |text| text.ends_with(": hello from tcp")).await;
+
+    ws.send(Message::text("/join web")).await.unwrap();
+    next_text(&mut ws, |text| text == "You joined web").await;
+}
+
+#[tokio::test]
+async fn websocket_port_serves_the_test_page() {
+    let (_, ws_addr) = spawn_ws_server().await;
+    let mut tcp = TcpStream::connect(ws_addr).await.unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with(INDEX_HTML));
+
+    let mut tcp = TcpStream::connect(ws_addr).await.unwrap();
+    tcp.write_all(b"GET /nope HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{response}");
+}
+
+#[tokio::test]
+async fn websocket_clients_can_use_json() {
+    let (_, ws_addr) = spawn_ws_server().await;
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{ws_addr}/")).await.unwrap();
+    ws.send(Message::text("/protocol json")).await.unwrap();
+    next_text(&mut ws, |text| text.starts_with(r#"{"type":"welcome""#)).await;
+    ws.send(Message::text(r#"{"type":"join","room":"web"}"#)).await.unwrap();
+    next_text(&mut ws, |text| text.starts_with(r#"{"type":"joined","room":"web""#)).await;
+}